@@ -6,7 +6,8 @@ mod benches {
         extern crate cernan;
 
 
-        use self::cernan::filter::{Filter, ProgrammableFilter, ProgrammableFilterConfig};
+        use self::cernan::filter::{ErrorPolicy, Filter, ProgrammableFilter,
+                                   ProgrammableFilterConfig};
         use self::cernan::metric;
         use self::test::Bencher;
         use std::path::PathBuf;
@@ -18,22 +19,141 @@ mod benches {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.collectd_scrub".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig = "collectd.cernan-llrv-prod-b3fbb697.protocols-TcpExt.\
                         protocol_counter-TCPFastOpenActive";
 
+            let mut events = Vec::new();
             b.iter(|| {
                 let metric = metric::Telemetry::new(orig, 12.0);
                 let event = metric::Event::new_telemetry(metric);
-                let res = cs.process(event);
+                let res = cs.process(event, &mut events);
                 assert!(res.is_ok());
+                events.clear();
             });
         }
 
+        #[bench]
+        fn bench_collectd_extraction_batch(b: &mut Bencher) {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/collectd_scrub.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                batch_size: 64,
+                config_path: "filters.collectd_scrub".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig = "collectd.cernan-llrv-prod-b3fbb697.protocols-TcpExt.\
+                        protocol_counter-TCPFastOpenActive";
+
+            let mut events = Vec::new();
+            b.iter(|| {
+                let batch = (0..64)
+                    .map(|_| metric::Event::new_telemetry(metric::Telemetry::new(orig, 12.0)))
+                    .collect();
+                let res = cs.process_batch(batch, &mut events);
+                assert!(res.is_ok());
+                events.clear();
+            });
+        }
+
+        #[bench]
+        fn bench_collectd_extraction_unbatched(b: &mut Bencher) {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/collectd_scrub.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                config_path: "filters.collectd_scrub".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig = "collectd.cernan-llrv-prod-b3fbb697.protocols-TcpExt.\
+                        protocol_counter-TCPFastOpenActive";
+
+            let mut events = Vec::new();
+            b.iter(|| {
+                for _ in 0..64 {
+                    let metric = metric::Telemetry::new(orig, 12.0);
+                    let res = cs.process(metric::Event::new_telemetry(metric), &mut events);
+                    assert!(res.is_ok());
+                }
+                events.clear();
+            });
+        }
+    }
+
+    mod parse {
+        extern crate test;
+        extern crate cernan;
+
+        use self::cernan::filter::{Filter, Grok, Parse, ParseConfig};
+        use self::cernan::metric;
+        use self::test::Bencher;
+
+        fn parse(pattern: &str) -> Parse {
+            let config = ParseConfig {
+                patterns: vec![Grok::default().compile(pattern).unwrap()],
+                forwards: Vec::new(),
+                config_path: "filters.parse".to_string(),
+                tags: Default::default(),
+            };
+            Parse::new(config)
+        }
+
+        fn bench_line(b: &mut Bencher, filter: &mut Parse, line: &str) {
+            let mut events = Vec::new();
+            b.iter(|| {
+                let log = metric::LogLine::new("access.log", line);
+                let event = metric::Event::new_log(log);
+                let res = filter.process(event, &mut events);
+                assert!(res.is_ok());
+                events.clear();
+            });
+        }
+
+        #[bench]
+        fn bench_nginx_access(b: &mut Bencher) {
+            let mut filter = parse("%{NGINXACCESS}");
+            bench_line(b,
+                       &mut filter,
+                       "10.0.0.2 - - [01/Mar/2017:10:00:00 +0000] \"POST /v1/orders \
+                        HTTP/1.1\" 201 512 \"-\" \"curl/7.51.0\" 0.042");
+        }
+
+        #[bench]
+        fn bench_apache_combined(b: &mut Bencher) {
+            let mut filter = parse("%{COMBINEDAPACHELOG}");
+            bench_line(b,
+                       &mut filter,
+                       "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /apache_pb.gif \
+                        HTTP/1.0\" 200 2326 \"http://www.example.com/start.html\" \
+                        \"Mozilla/4.08\"");
+        }
+
+        #[bench]
+        fn bench_syslog(b: &mut Bencher) {
+            let mut filter = parse("%{SYSLOGLINE}");
+            bench_line(b,
+                       &mut filter,
+                       "Mar  1 10:00:00 web-1 sshd[4242]: Accepted publickey for deploy");
+        }
+
+        #[bench]
+        fn bench_no_match(b: &mut Bencher) {
+            let mut filter = parse("%{NGINXACCESS}");
+            bench_line(b, &mut filter, "this line is not an access log entry at all");
+        }
     }
 }
@@ -12,3 +12,14 @@ end
 
 function tick(pyld)
 end 
+
+
+function process_batch(pyld)
+   for i = 1, payload.metric_count(pyld) do
+      local old_name = payload.metric_name(pyld, i)
+      local collectd, rest = string.match(old_name, "^(collectd)[%.@][%w_-]+(.*)")
+      if collectd ~= nil then
+         payload.set_metric_name(pyld, i, string.format("%s%s", collectd, rest))
+      end
+   end
+end
@@ -0,0 +1,22 @@
+function process_metric(pyld)
+   local name = payload.metric_name(pyld, 1)
+   local copy = payload.clone_metric(pyld, 1)
+   payload.set_metric_name(pyld, copy, string.format("%s.copy", name))
+   payload.metric_set_aggr(pyld, copy, "sum")
+   payload.metric_set_timestamp(pyld, copy, 10101)
+   payload.metric_set_persist(pyld, copy, true)
+end
+
+function process_log(pyld)
+   local path = payload.log_path(pyld, 1)
+   payload.log_set_value(pyld, 1, string.upper(payload.log_value(pyld, 1)))
+   payload.log_set_path(pyld, 1, string.format("%s.upper", path))
+end
+
+function tick(pyld)
+   payload.push_metric(pyld, {name = "constructed",
+                              value = 2.5,
+                              aggr = "set",
+                              timestamp = 20202,
+                              tags = {source = "tick"}})
+end
@@ -0,0 +1,14 @@
+function process_metric(pyld)
+   if payload.metric_name(pyld, 1) == "drop.me" then
+      payload.drop_metric(pyld, 1)
+   end
+end
+
+function process_log(pyld)
+   if string.find(payload.log_value(pyld, 1), "DEBUG") ~= nil then
+      payload.drop_log(pyld, 1)
+   end
+end
+
+function tick(pyld)
+end
@@ -0,0 +1,16 @@
+function process_metric(pyld)
+   while true do
+   end
+end
+
+function process_log(pyld)
+   local hoard = {}
+   local i = 1
+   while true do
+      hoard[i] = string.rep("x", 1024) .. i
+      i = i + 1
+   end
+end
+
+function tick(pyld)
+end
@@ -0,0 +1,21 @@
+-- Calls the payload function named by the log line without the string
+-- argument it needs, and in tick pushes a metric table without a name.
+function process_log(pyld)
+   local func = payload.log_value(pyld, 1)
+   if func == "push_log" then
+      payload.push_log(pyld)
+   elseif func == "push_metric" then
+      payload.push_metric(pyld, nil, 1.0)
+   else
+      payload[func](pyld, 1)
+   end
+end
+
+function process_metric(pyld)
+   payload.metric_set_aggr(pyld, 1)
+end
+
+function tick(pyld)
+   payload.push_metric(pyld, {value = 1.0})
+   payload.push_metric(pyld, {name = "named", value = 2.0, timestamp = 10})
+end
@@ -0,0 +1,3 @@
+function process_metric(pyld)
+   error("no metric is welcome here")
+end
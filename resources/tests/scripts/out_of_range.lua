@@ -0,0 +1,6 @@
+-- Calls the payload function named by the log line with an index past the
+-- end of the payload.
+function process_log(pyld)
+   local func = payload.log_value(pyld, 1)
+   payload[func](pyld, 2)
+end
@@ -0,0 +1,17 @@
+function process_metric(pyld)
+   local total = payload.state_incr(pyld, "total", payload.metric_value(pyld, 1))
+   payload.state_set(pyld, "last_seen", payload.metric_name(pyld, 1))
+   payload.drop_metric(pyld, 1)
+end
+
+function process_log(pyld)
+end
+
+function tick(pyld)
+   local total = payload.state_get(pyld, "total")
+   if total ~= nil then
+      payload.push_metric(pyld, {name = "running_total",
+                                 value = total,
+                                 tags = {last_seen = payload.state_get(pyld, "last_seen")}})
+   end
+end
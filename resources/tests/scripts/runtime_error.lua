@@ -0,0 +1,12 @@
+function process_metric(pyld)
+   payload.metric_set_tag(pyld, 1, "half", "done")
+   error("metric went wrong")
+end
+
+function process_log(pyld)
+   local missing = nil
+   return missing.field
+end
+
+function tick(pyld)
+end
@@ -0,0 +1,15 @@
+function process_metric(pyld)
+   local reachable = {}
+   if io ~= nil then table.insert(reachable, "io") end
+   if os.execute ~= nil then table.insert(reachable, "os.execute") end
+   if require ~= nil then table.insert(reachable, "require") end
+   if dofile ~= nil then table.insert(reachable, "dofile") end
+   if debug ~= nil then table.insert(reachable, "debug") end
+   payload.metric_set_tag(pyld, 1, "reachable", table.concat(reachable, ","))
+end
+
+function process_log(pyld)
+end
+
+function tick(pyld)
+end
@@ -0,0 +1,20 @@
+function scrub(tags)
+   local scrubbed = {}
+   for key, value in pairs(tags) do
+      if string.find(key, "^k8s_") == nil then
+         scrubbed[string.lower(key)] = value
+      end
+   end
+   return scrubbed
+end
+
+function process_metric(pyld)
+   payload.metric_set_tags(pyld, 1, scrub(payload.metric_tags(pyld, 1)))
+end
+
+function process_log(pyld)
+   payload.log_set_tags(pyld, 1, scrub(payload.log_tags(pyld, 1)))
+end
+
+function tick(pyld)
+end
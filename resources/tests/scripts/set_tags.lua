@@ -0,0 +1,7 @@
+function process_metric(pyld)
+   payload.metric_set_tags(pyld, 1, {env = "prod", shard = 7, [1] = "skipped"})
+end
+
+function process_log(pyld)
+   payload.log_set_tags(pyld, 1, {env = "prod", shard = 7, [1] = "skipped"})
+end
@@ -0,0 +1,3 @@
+function process_metric(pyld)
+   payload.metric_set_tag(pyld, 1, "bizz"
+end
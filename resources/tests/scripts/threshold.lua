@@ -0,0 +1,14 @@
+-- Shared between filters: each sets its own threshold and label in args
+function process_metric(pyld)
+   if payload.metric_value(pyld, 1) < config.threshold then
+      payload.drop_metric(pyld, 1)
+   else
+      payload.metric_set_tag(pyld, 1, "label", config.label)
+   end
+end
+
+function process_log(pyld)
+end
+
+function tick(pyld)
+end
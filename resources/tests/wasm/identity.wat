@@ -0,0 +1,14 @@
+;; Source of identity.wasm. Passes metrics on unchanged, counting them in
+;; the state key "seen", and drops logs.
+(module
+  (import "cernan" "emit" (func $emit (param i32 i32)))
+  (import "cernan" "state_incr" (func $state_incr (param i32 i32 f64) (result f64)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "seen")
+  (func (export "cernan_alloc") (param $len i32) (result i32)
+    i32.const 1024)
+  (func (export "process_metric") (param $ptr i32) (param $len i32)
+    (call $emit (local.get $ptr) (local.get $len))
+    (drop (call $state_incr (i32.const 0) (i32.const 4) (f64.const 1))))
+  (func (export "process_log") (param $ptr i32) (param $len i32))
+  (func (export "tick") (param $ptr i32) (param $len i32)))
@@ -0,0 +1,13 @@
+;; Source of spin.wasm. Never returns from process_metric or tick, so can
+;; only be stopped by an instruction limit.
+(module
+  (memory (export "memory") 1)
+  (func (export "cernan_alloc") (param $len i32) (result i32)
+    i32.const 1024)
+  (func (export "process_metric") (param $ptr i32) (param $len i32)
+    (loop $spin
+      (br $spin)))
+  (func (export "process_log") (param $ptr i32) (param $len i32))
+  (func (export "tick") (param $ptr i32) (param $len i32)
+    (loop $spin
+      (br $spin))))
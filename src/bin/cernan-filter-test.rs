@@ -0,0 +1,207 @@
+//! Run a programmable filter outside of cernan.
+//!
+//! Events are read one per line from a file, or stdin, and fed to the
+//! filter. Once the input is exhausted the filter is flushed, running the
+//! script's `tick` if it has one, and every event the filter produced is
+//! printed to stdout as a line of JSON. The exit status is 2 if the script
+//! failed on any event. See `cernan::filter::parse_event` for the accepted
+//! input formats.
+extern crate cernan;
+extern crate clap;
+extern crate libc;
+
+use cernan::filter::{Filter, FilterError, ProgrammableFilter, ProgrammableFilterConfig};
+use cernan::metric;
+use clap::{App, Arg};
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn read_config(config_file: &str, name: &str) -> ProgrammableFilterConfig {
+    let mut buffer = String::new();
+    if let Err(e) = fs::File::open(config_file).and_then(|mut fp| fp.read_to_string(&mut buffer)) {
+        let _ = writeln!(io::stderr(), "could not read {}: {}", config_file, e);
+        process::exit(1);
+    }
+    let mut args = cernan::config::parse_config_file(buffer, 0);
+    let config_path = if name.starts_with("filters.") {
+        name.to_string()
+    } else {
+        format!("filters.{}", name)
+    };
+    match args.filters.remove(&config_path) {
+        Some(config) => config,
+        None => {
+            let _ = writeln!(io::stderr(),
+                             "no programmable filter {} in {}",
+                             config_path,
+                             config_file);
+            process::exit(1);
+        }
+    }
+}
+
+/// Collect the events produced by one call into the filter, as
+/// `cernan::filter` would forward them. A failed call's own output is
+/// discarded and the events carried by the error take its place.
+fn collect(res: Result<(), FilterError>,
+           events: &mut Vec<metric::Event>,
+           output: &mut Vec<metric::Event>,
+           errors: &mut usize) {
+    match res {
+        Ok(()) => output.extend(events.drain(..)),
+        Err(fe) => {
+            *errors += 1;
+            events.clear();
+            match fe {
+                FilterError::NoSuchFunction(func, event) => {
+                    let _ = writeln!(io::stderr(), "script does not define {}", func);
+                    output.push(event);
+                }
+                FilterError::ScriptError(msg, evs) => {
+                    let _ = writeln!(io::stderr(), "script error: {}", msg);
+                    output.extend(evs);
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let args = App::new("cernan-filter-test")
+        .about("run a programmable filter over events read from a file")
+        .arg(Arg::with_name("config-file")
+            .long("config")
+            .short("C")
+            .value_name("config")
+            .help("The cernan config file defining the filter.")
+            .requires("filter")
+            .takes_value(true))
+        .arg(Arg::with_name("filter")
+            .long("filter")
+            .short("f")
+            .value_name("name")
+            .help("The name of the filter in the config file.")
+            .requires("config-file")
+            .takes_value(true))
+        .arg(Arg::with_name("script")
+            .long("script")
+            .short("s")
+            .value_name("script")
+            .help("A script to run with default settings, in place of a config file.")
+            .conflicts_with("config-file")
+            .takes_value(true))
+        .arg(Arg::with_name("data-directory")
+            .long("data-directory")
+            .value_name("dir")
+            .help("Where the filter keeps state. Defaults to a fresh temporary directory.")
+            .takes_value(true))
+        .arg(Arg::with_name("input")
+            .help("The file of events to read, one per line. Defaults to stdin.")
+            .index(1))
+        .get_matches();
+
+    let mut config = match (args.value_of("config-file"), args.value_of("filter")) {
+        (Some(config_file), Some(name)) => read_config(config_file, name),
+        _ => {
+            match args.value_of("script") {
+                Some(script) => {
+                    ProgrammableFilterConfig {
+                        script: PathBuf::from(script),
+                        config_path: "filters.filter_test".to_string(),
+                        ..Default::default()
+                    }
+                }
+                None => {
+                    let _ = writeln!(io::stderr(), "one of --config or --script is required");
+                    process::exit(1);
+                }
+            }
+        }
+    };
+
+    // State written by a test run must never clobber that of a running
+    // cernan, nor leak between runs.
+    let scratch = match args.value_of("data-directory") {
+        Some(dir) => {
+            config.data_directory = Path::new(dir).to_path_buf();
+            None
+        }
+        None => {
+            let dir = env::temp_dir()
+                .join(format!("cernan-filter-test-{}", unsafe { libc::getpid() }));
+            config.data_directory = dir.clone();
+            Some(dir)
+        }
+    };
+
+    let mut filter = match ProgrammableFilter::new(config) {
+        Ok(filter) => filter,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "could not load filter: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let input: Box<BufRead> = match args.value_of("input") {
+        None | Some("-") => Box::new(io::BufReader::new(io::stdin())),
+        Some(path) => {
+            match fs::File::open(path) {
+                Ok(fp) => Box::new(io::BufReader::new(fp)),
+                Err(e) => {
+                    let _ = writeln!(io::stderr(), "could not open {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+        }
+    };
+
+    let mut events = Vec::new();
+    for line in input.lines() {
+        match line {
+            Ok(line) => events.extend(cernan::filter::parse_event(&line)),
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "could not read input: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut errors = 0;
+    let mut output = Vec::new();
+    let mut res_events = Vec::new();
+    let batch_size = filter.batch_size();
+    if batch_size > 1 {
+        while !events.is_empty() {
+            let rest = events.split_off(batch_size.min(events.len()));
+            let res = filter.process_batch(events, &mut res_events);
+            collect(res, &mut res_events, &mut output, &mut errors);
+            events = rest;
+        }
+    } else {
+        for event in events {
+            let res = filter.process(event, &mut res_events);
+            collect(res, &mut res_events, &mut output, &mut errors);
+        }
+    }
+    let res = filter.process(metric::Event::TimerFlush, &mut res_events);
+    collect(res, &mut res_events, &mut output, &mut errors);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for event in &output {
+        if let Some(line) = cernan::filter::render_event(event) {
+            let _ = writeln!(stdout, "{}", line);
+        }
+    }
+
+    if let Some(dir) = scratch {
+        let _ = fs::remove_dir_all(dir);
+    }
+    if errors > 0 {
+        process::exit(2);
+    }
+}
@@ -5,7 +5,9 @@ extern crate fern;
 extern crate log;
 extern crate hopper;
 
-use cernan::filter::{Filter, ProgrammableFilterConfig};
+use cernan::filter::{AggregateConfig, CardinalityLimitConfig, DedupConfig, DerivativeConfig, Filter,
+                     JsonDecodeConfig, LogMetricsConfig, ParseConfig, ProgrammableFilterConfig,
+                     RewriteConfig, RouterConfig, ThrottleConfig, WasmFilterConfig};
 use cernan::metric;
 use cernan::sink::{FirehoseConfig, Sink};
 use cernan::source::Source;
@@ -35,6 +37,31 @@ fn populate_forwards(sends: &mut util::Channel,
     }
 }
 
+#[cfg(feature = "wasm")]
+fn run_wasm_filter(config: WasmFilterConfig,
+                   recv: hopper::Receiver<metric::Event>,
+                   sends: util::Channel)
+                   -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        match cernan::filter::WasmFilter::new(config) {
+            Ok(mut filter) => filter.run(recv, sends),
+            Err(e) => {
+                error!("could not start filter: {}", e);
+                process::exit(1);
+            }
+        }
+    })
+}
+
+#[cfg(not(feature = "wasm"))]
+fn run_wasm_filter(config: WasmFilterConfig,
+                   _: hopper::Receiver<metric::Event>,
+                   _: util::Channel)
+                   -> thread::JoinHandle<()> {
+    error!("cannot run {}: cernan was built without the wasm feature", config.config_path);
+    process::exit(1);
+}
+
 fn main() {
     let args = cernan::config::parse_args();
 
@@ -141,18 +168,186 @@ fn main() {
 
     // FILTERS
     //
+    // Filters may forward to one another so every filter's channel is made
+    // before any filter is started.
+    let mut filter_recvs = HashMap::new();
+    for config_path in args.filters
+        .keys()
+        .chain(args.parse_filters.keys())
+        .chain(args.json_decode_filters.keys())
+        .chain(args.log_metrics_filters.keys())
+        .chain(args.rewrite_filters.keys())
+        .chain(args.router_filters.keys())
+        .chain(args.throttle_filters.keys())
+        .chain(args.dedup_filters.keys())
+        .chain(args.cardinality_filters.keys())
+        .chain(args.aggregate_filters.keys())
+        .chain(args.derivative_filters.keys())
+        .chain(args.wasm_filters.keys()) {
+        let (flt_send, flt_recv) = hopper::channel(config_path, &args.data_directory).unwrap();
+        // Every filter is sent TimerFlush. Those holding events between
+        // flushes emit them then and a programmable filter runs its script's
+        // `tick`, if defined. The rest ignore it.
+        flush_sends.push(flt_send.clone());
+        sends.insert(config_path.clone(), flt_send);
+        filter_recvs.insert(config_path.clone(), flt_recv);
+    }
+    if !args.filters.is_empty() {
+        cernan::filter::reload_on_sighup();
+    }
     for config in args.filters.values() {
         let c: ProgrammableFilterConfig = (*config).clone();
-        let (flt_send, flt_recv) = hopper::channel(&config.config_path, &args.data_directory)
-            .unwrap();
-        sends.insert(config.config_path.clone(), flt_send);
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            // Nothing would drain the filter's channel were it not to start.
+            match cernan::filter::ProgrammableFilter::new(c) {
+                Ok(mut filter) => filter.run(flt_recv, downstream_sends),
+                Err(e) => {
+                    error!("could not start filter: {}", e);
+                    process::exit(1);
+                }
+            }
+        }));
+    }
+    for config in args.parse_filters.values() {
+        let c: ParseConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::Parse::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.json_decode_filters.values() {
+        let c: JsonDecodeConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::JsonDecode::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.log_metrics_filters.values() {
+        let c: LogMetricsConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::LogMetrics::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.rewrite_filters.values() {
+        let c: RewriteConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::Rewrite::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.throttle_filters.values() {
+        let c: ThrottleConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::Throttle::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.dedup_filters.values() {
+        let c: DedupConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::Dedup::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.cardinality_filters.values() {
+        let c: CardinalityLimitConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::CardinalityLimit::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.aggregate_filters.values() {
+        let c: AggregateConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
         let mut downstream_sends = Vec::new();
         populate_forwards(&mut downstream_sends,
                           &config.forwards,
                           &config.config_path,
                           &sends);
         joins.push(thread::spawn(move || {
-            cernan::filter::ProgrammableFilter::new(c).run(flt_recv, downstream_sends);
+            cernan::filter::Aggregate::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.derivative_filters.values() {
+        let c: DerivativeConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::Derivative::new(c).run(flt_recv, downstream_sends);
+        }));
+    }
+    for config in args.wasm_filters.values() {
+        let c: WasmFilterConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut downstream_sends = Vec::new();
+        populate_forwards(&mut downstream_sends,
+                          &config.forwards,
+                          &config.config_path,
+                          &sends);
+        joins.push(run_wasm_filter(c, flt_recv, downstream_sends));
+    }
+    for config in args.router_filters.values() {
+        let c: RouterConfig = (*config).clone();
+        let flt_recv = filter_recvs.remove(&config.config_path).unwrap();
+        let mut route_sends = Vec::new();
+        for route in &config.routes {
+            let mut downstream_sends = Vec::new();
+            populate_forwards(&mut downstream_sends,
+                              &route.forwards,
+                              &config.config_path,
+                              &sends);
+            route_sends.push(downstream_sends);
+        }
+        let mut default_sends = Vec::new();
+        populate_forwards(&mut default_sends, &config.default, &config.config_path, &sends);
+        joins.push(thread::spawn(move || {
+            cernan::filter::Router::new(c).run(flt_recv, route_sends, default_sends);
         }));
     }
 
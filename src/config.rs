@@ -4,22 +4,31 @@
 //! the server can consume and use as configuration data.
 
 use clap::{App, Arg};
-use metric::TagMap;
+use glob::Pattern;
+use metric::{AggregationMethod, TagMap};
+use regex::Regex;
 use rusoto::Region;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 use toml;
 use toml::Value;
 
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
-use super::filter::ProgrammableFilterConfig;
+use super::filter::{AggregateConfig, CardinalityAction, CardinalityLimitConfig, DedupConfig,
+                    DerivativeConfig, DerivativeMode, ErrorPolicy, EventKind, Grok,
+                    JsonDecodeConfig, LogMetric, LogMetricsConfig, NameMatch, ParseConfig,
+                    ProgrammableFilterConfig, RewriteAction, RewriteConfig, RewriteRule, Route,
+                    RouterConfig, ScriptArg, ThrottleConfig, ThrottleKey, ThrottleMode,
+                    WasmFilterConfig};
 use super::sink::{ConsoleConfig, FirehoseConfig, InfluxDBConfig, NativeConfig, NullConfig,
                   PrometheusConfig, WavefrontConfig};
-use super::source::{FileServerConfig, GraphiteConfig, NativeServerConfig, StatsdConfig};
+use super::source::{Encoding, FileServerConfig, GraphiteConfig, MultilineConfig,
+                     NativeServerConfig, StartPosition, StatsdConfig};
 
 #[derive(Debug)]
 pub struct Args {
@@ -29,6 +38,17 @@ pub struct Args {
     pub prometheus: Option<PrometheusConfig>,
     pub files: Vec<FileServerConfig>,
     pub filters: HashMap<String, ProgrammableFilterConfig>,
+    pub parse_filters: HashMap<String, ParseConfig>,
+    pub json_decode_filters: HashMap<String, JsonDecodeConfig>,
+    pub log_metrics_filters: HashMap<String, LogMetricsConfig>,
+    pub rewrite_filters: HashMap<String, RewriteConfig>,
+    pub router_filters: HashMap<String, RouterConfig>,
+    pub throttle_filters: HashMap<String, ThrottleConfig>,
+    pub dedup_filters: HashMap<String, DedupConfig>,
+    pub cardinality_filters: HashMap<String, CardinalityLimitConfig>,
+    pub aggregate_filters: HashMap<String, AggregateConfig>,
+    pub derivative_filters: HashMap<String, DerivativeConfig>,
+    pub wasm_filters: HashMap<String, WasmFilterConfig>,
     pub firehosen: Vec<FirehoseConfig>,
     pub flush_interval: u64,
     pub graphites: HashMap<String, GraphiteConfig>,
@@ -140,6 +160,17 @@ pub fn parse_args() -> Args {
                 firehosen: Vec::default(),
                 files: Default::default(),
                 filters: Default::default(),
+                parse_filters: Default::default(),
+                json_decode_filters: Default::default(),
+                log_metrics_filters: Default::default(),
+                rewrite_filters: Default::default(),
+                router_filters: Default::default(),
+                throttle_filters: Default::default(),
+                dedup_filters: Default::default(),
+                cardinality_filters: Default::default(),
+                aggregate_filters: Default::default(),
+                derivative_filters: Default::default(),
+                wasm_filters: Default::default(),
                 verbose: verb,
                 version: VERSION.unwrap().to_string(),
             }
@@ -147,6 +178,217 @@ pub fn parse_args() -> Args {
     }
 }
 
+fn parse_grok(tbl: &Value) -> Grok {
+    let mut grok = Grok::default();
+    if let Some(custom) = tbl.lookup("custom_patterns") {
+        for (name, pattern) in custom.as_table()
+            .expect("custom_patterns must be a table")
+            .iter() {
+            grok.insert(name.as_str(),
+                        pattern.as_str().expect("custom pattern must be a string"));
+        }
+    }
+    grok
+}
+
+fn compile_grok(grok: &Grok, pattern: &Value) -> Regex {
+    let pattern = pattern.as_str().expect("pattern must be a string");
+    match grok.compile(pattern) {
+        Ok(re) => re,
+        Err(e) => panic!("could not compile pattern {}: {}", pattern, e),
+    }
+}
+
+/// What a script filter does with events when its script fails
+fn parse_on_error(tbl: &Value) -> ErrorPolicy {
+    match tbl.lookup("on_error").map(|p| p.as_str().expect("on_error must be a string")) {
+        None | Some("pass_through") => ErrorPolicy::PassThrough,
+        Some("drop") => ErrorPolicy::Drop,
+        Some(other) => panic!("unknown on_error policy {}", other),
+    }
+}
+
+fn number(tbl: &Value, key: &str) -> Option<f64> {
+    tbl.lookup(key).map(|v| match *v {
+        Value::Integer(i) => i as f64,
+        Value::Float(f) => f,
+        _ => panic!("{} must be a number", key),
+    })
+}
+
+fn string_table(tbl: &Value, key: &str) -> Vec<(String, String)> {
+    match tbl.lookup(key) {
+        Some(t) => {
+            t.as_table()
+                .expect(&format!("{} must be a table", key))
+                .iter()
+                .map(|(k, v)| {
+                    (k.clone(), v.as_str().expect(&format!("{} must be strings", key)).to_string())
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+fn string_array(tbl: &Value, key: &str) -> Vec<String> {
+    match tbl.lookup(key) {
+        Some(a) => {
+            a.as_slice()
+                .expect(&format!("{} must be an array", key))
+                .iter()
+                .map(|v| v.as_str().expect(&format!("{} must be strings", key)).to_string())
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+fn parse_rewrite_rule(tbl: &Value) -> RewriteRule {
+    let name = match (tbl.lookup("name"), tbl.lookup("name_regex")) {
+        (Some(_), Some(_)) => panic!("rewrite rule may have only one of name and name_regex"),
+        (Some(glob), None) => {
+            let glob = glob.as_str().expect("name must be a string");
+            Some(NameMatch::Glob(Pattern::new(glob)
+                .expect(&format!("could not compile name glob {}", glob))))
+        }
+        (None, Some(re)) => {
+            let re = re.as_str().expect("name_regex must be a string");
+            Some(NameMatch::Regex(Regex::new(re)
+                .expect(&format!("could not compile name_regex {}", re))))
+        }
+        (None, None) => None,
+    };
+
+    let mut tags: Vec<(String, Option<String>)> = string_table(tbl, "tags")
+        .into_iter()
+        .map(|(k, v)| (k, Some(v)))
+        .collect();
+    for key in string_array(tbl, "has_tags") {
+        tags.push((key, None));
+    }
+
+    let mut actions = Vec::new();
+    if let Some(rename) = tbl.lookup("rename") {
+        let rename = rename.as_str().expect("rename must be a string");
+        actions.push(RewriteAction::RenameMetric(rename.to_string()));
+    }
+    for (from, to) in string_table(tbl, "rename_tags") {
+        actions.push(RewriteAction::RenameTag(from, to));
+    }
+    for (key, val) in string_table(tbl, "set_tags") {
+        actions.push(RewriteAction::SetTag(key, val));
+    }
+    for key in string_array(tbl, "remove_tags") {
+        actions.push(RewriteAction::RemoveTag(key));
+    }
+    if tbl.lookup("drop").map_or(false, |d| d.as_bool().expect("drop must be a boolean")) {
+        actions.push(RewriteAction::Drop);
+    }
+
+    RewriteRule {
+        name: name,
+        tags: tags,
+        actions: actions,
+    }
+}
+
+fn parse_file_server_config(tbl: &Value,
+                            tags: &TagMap,
+                            data_directory: &Path)
+                            -> Option<FileServerConfig> {
+    match tbl.lookup("path") {
+        Some(pth) => {
+            let path = Path::new(pth.as_str().unwrap());
+            let fwds = match tbl.lookup("forwards") {
+                Some(fwds) => {
+                    fwds.as_slice()
+                        .expect("forwards must be an array")
+                        .to_vec()
+                        .iter()
+                        .map(|s| s.as_str().unwrap().to_string())
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+            let start_position = match tbl.lookup("start_position") {
+                Some(pos) => {
+                    match pos.as_str().expect("start_position must be a string") {
+                        "beginning" => StartPosition::Beginning,
+                        "end" => StartPosition::End,
+                        "checkpoint" => StartPosition::Checkpoint,
+                        other => {
+                            panic!("start_position must be one of beginning, end or \
+                                    checkpoint, not {}",
+                                   other)
+                        }
+                    }
+                }
+                None => StartPosition::default(),
+            };
+            let multiline = match tbl.lookup("multiline") {
+                Some(ml) => {
+                    let pattern = |key: &str| {
+                        ml.lookup(key).map(|p| {
+                            let p = p.as_str().expect("multiline patterns must be strings");
+                            Regex::new(p).expect("multiline pattern is not a valid regex")
+                        })
+                    };
+                    let config = MultilineConfig {
+                        start_pattern: pattern("start_pattern"),
+                        continuation_pattern: pattern("continuation_pattern"),
+                        flush_timeout: Duration::from_millis(ml.lookup("flush_timeout_ms")
+                            .unwrap_or(&Value::Integer(1000))
+                            .as_integer()
+                            .expect("flush_timeout_ms must be an integer") as u64),
+                    };
+                    if config.start_pattern.is_none() && config.continuation_pattern.is_none() {
+                        panic!("multiline requires a start_pattern or continuation_pattern");
+                    }
+                    Some(config)
+                }
+                None => None,
+            };
+            let max_open_files = tbl.lookup("max_open_files")
+                .unwrap_or(&Value::Integer(1024))
+                .as_integer()
+                .expect("max_open_files must be an integer") as usize;
+            let idle_timeout = tbl.lookup("idle_timeout_secs").map(|t| {
+                Duration::from_secs(t.as_integer().expect("idle_timeout_secs must be an integer") as
+                                    u64)
+            });
+            let encoding = match tbl.lookup("encoding") {
+                Some(enc) => {
+                    match enc.as_str().expect("encoding must be a string") {
+                        "utf8" | "utf-8" => Encoding::Utf8Lossy,
+                        "utf8-strict" | "utf-8-strict" => Encoding::Utf8Strict,
+                        "latin1" | "iso-8859-1" => Encoding::Latin1,
+                        other => {
+                            panic!("encoding must be one of utf8, utf8-strict or latin1, not {}",
+                                   other)
+                        }
+                    }
+                }
+                None => Encoding::default(),
+            };
+            let path_buf = path.to_path_buf();
+            Some(FileServerConfig {
+                path: path_buf.clone(),
+                tags: tags.clone(),
+                forwards: fwds,
+                config_path: format!("sources.files.{}", path_buf.to_str().unwrap()),
+                start_position: start_position,
+                data_directory: data_directory.to_path_buf(),
+                multiline: multiline,
+                max_open_files: max_open_files,
+                idle_timeout: idle_timeout,
+                encoding: encoding,
+            })
+        }
+        None => None,
+    }
+}
+
 pub fn parse_config_file(buffer: String, verbosity: u64) -> Args {
     let value: toml::Value = buffer.parse().unwrap();
 
@@ -156,6 +398,12 @@ pub fn parse_config_file(buffer: String, verbosity: u64) -> Args {
         .map(|s| Path::new(s).to_path_buf())
         .unwrap();
 
+    let data_directory: PathBuf = value.lookup("data-directory")
+        .unwrap_or(&Value::String("/tmp/cernan-data".to_string()))
+        .as_str()
+        .map(|s| Path::new(s).to_path_buf())
+        .unwrap();
+
     let tags: TagMap = match value.lookup("tags") {
         Some(tbl) => {
             let mut tags = TagMap::default();
@@ -287,99 +535,411 @@ pub fn parse_config_file(buffer: String, verbosity: u64) -> Args {
     match value.lookup("file") {
         Some(array) => {
             for tbl in array.as_slice().unwrap() {
-                match tbl.lookup("path") {
-                    Some(pth) => {
-                        let path = Path::new(pth.as_str().unwrap());
-                        let fwds = match tbl.lookup("forwards") {
-                            Some(fwds) => {
-                                fwds.as_slice()
-                                    .expect("forwards must be an array")
-                                    .to_vec()
-                                    .iter()
-                                    .map(|s| s.as_str().unwrap().to_string())
-                                    .collect()
-                            }
-                            None => Vec::new(),
-                        };
-                        let path_buf = path.to_path_buf();
-                        let config = FileServerConfig {
-                            path: path_buf.clone(),
-                            tags: tags.clone(),
-                            forwards: fwds,
-                            config_path: format!("sources.files.{}", path_buf.to_str().unwrap()),
-                        };
-                        files.push(config)
-                    }
-                    None => continue,
+                if let Some(config) = parse_file_server_config(tbl, &tags, &data_directory) {
+                    files.push(config)
                 }
             }
         }
         None => {
             if let Some(tbls) = value.lookup("sources.files") {
                 for tbl in tbls.as_table().unwrap().values() {
-                    match tbl.lookup("path") {
+                    if let Some(config) = parse_file_server_config(tbl, &tags, &data_directory) {
+                        files.push(config)
+                    }
+                }
+            }
+        }
+    }
+
+    let mut filters: HashMap<String, ProgrammableFilterConfig> = HashMap::new();
+    let mut parse_filters: HashMap<String, ParseConfig> = HashMap::new();
+    let mut json_decode_filters: HashMap<String, JsonDecodeConfig> = HashMap::new();
+    let mut log_metrics_filters: HashMap<String, LogMetricsConfig> = HashMap::new();
+    let mut rewrite_filters: HashMap<String, RewriteConfig> = HashMap::new();
+    let mut router_filters: HashMap<String, RouterConfig> = HashMap::new();
+    let mut throttle_filters: HashMap<String, ThrottleConfig> = HashMap::new();
+    let mut dedup_filters: HashMap<String, DedupConfig> = HashMap::new();
+    let mut cardinality_filters: HashMap<String, CardinalityLimitConfig> = HashMap::new();
+    let mut aggregate_filters: HashMap<String, AggregateConfig> = HashMap::new();
+    let mut derivative_filters: HashMap<String, DerivativeConfig> = HashMap::new();
+    let mut wasm_filters: HashMap<String, WasmFilterConfig> = HashMap::new();
+    if let Some(tbls) = value.lookup("filters") {
+        for (name, tbl) in tbls.as_table().unwrap().iter() {
+            let config_path = format!("filters.{}", name);
+            let fwds = match tbl.lookup("forwards") {
+                Some(fwds) => {
+                    fwds.as_slice()
+                        .expect("forwards must be an array")
+                        .to_vec()
+                        .iter()
+                        .map(|s| s.as_str().unwrap().to_string())
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+            let filter_type = tbl.lookup("type")
+                .map(|t| t.as_str().expect("filter type must be a string"));
+            match filter_type {
+                None | Some("programmable") => {
+                    match tbl.lookup("script") {
                         Some(pth) => {
                             let path = Path::new(pth.as_str().unwrap());
-                            let fwds = match tbl.lookup("forwards") {
-                                Some(fwds) => {
-                                    fwds.as_slice()
-                                        .expect("forwards must be an array")
-                                        .to_vec()
-                                        .iter()
-                                        .map(|s| s.as_str().unwrap().to_string())
-                                        .collect()
+                            let default = ProgrammableFilterConfig::default();
+                            let batch_size = match tbl.lookup("batch_size") {
+                                Some(b) => {
+                                    b.as_integer().expect("batch_size must be an integer") as usize
                                 }
-                                None => Vec::new(),
+                                None => default.batch_size,
                             };
-                            let path_buf = path.to_path_buf();
-                            let config = FileServerConfig {
-                                path: path_buf.clone(),
-                                tags: tags.clone(),
+                            let mut script_args = HashMap::new();
+                            if let Some(tbl) = tbl.lookup("args") {
+                                for (key, val) in tbl.as_table()
+                                    .expect("args must be a table")
+                                    .iter() {
+                                    let arg = match *val {
+                                        Value::Boolean(b) => ScriptArg::Boolean(b),
+                                        Value::Float(f) => ScriptArg::Float(f),
+                                        Value::Integer(i) => ScriptArg::Integer(i),
+                                        Value::String(ref s) => ScriptArg::String(s.clone()),
+                                        _ => {
+                                            panic!("args value {} must be a string, number or \
+                                                    boolean",
+                                                   key)
+                                        }
+                                    };
+                                    script_args.insert(key.clone(), arg);
+                                }
+                            }
+                            let config = ProgrammableFilterConfig {
+                                // absolute paths are kept as-is by join
+                                script: scripts_dir.join(path),
+                                args: script_args,
+                                on_error: parse_on_error(tbl),
+                                sandbox: tbl.lookup("sandbox").map_or(default.sandbox, |s| {
+                                    s.as_bool().expect("sandbox must be a boolean")
+                                }),
+                                // zero lifts the limit
+                                instruction_limit: match tbl.lookup("instruction_limit") {
+                                    Some(l) => {
+                                        match l.as_integer()
+                                            .expect("instruction_limit must be an integer") {
+                                            0 => None,
+                                            l => Some(l as u32),
+                                        }
+                                    }
+                                    None => default.instruction_limit,
+                                },
+                                memory_limit: tbl.lookup("memory_limit").map(|l| {
+                                    l.as_integer().expect("memory_limit must be an integer") as
+                                    usize
+                                }),
+                                batch_size: batch_size,
+                                data_directory: data_directory.clone(),
                                 forwards: fwds,
-                                config_path: format!("sources.files.{}",
-                                                     path_buf.to_str().unwrap()),
+                                config_path: config_path.clone(),
+                                tags: tags.clone(),
                             };
-                            files.push(config)
+                            filters.insert(config_path, config);
                         }
                         None => continue,
                     }
                 }
-            }
-        }
-    }
-
-    let mut filters: HashMap<String, ProgrammableFilterConfig> = HashMap::new();
-    if let Some(tbls) = value.lookup("filters") {
-        for (name, tbl) in tbls.as_table().unwrap().iter() {
-            match tbl.lookup("script") {
-                Some(pth) => {
-                    let path = Path::new(pth.as_str().unwrap());
-                    let fwds = match tbl.lookup("forwards") {
-                        Some(fwds) => {
-                            fwds.as_slice()
-                                .expect("forwards must be an array")
-                                .to_vec()
-                                .iter()
-                                .map(|s| s.as_str().unwrap().to_string())
-                                .collect()
+                Some("parse") => {
+                    let grok = parse_grok(tbl);
+                    let patterns = tbl.lookup("patterns")
+                        .expect("parse filter must have patterns")
+                        .as_slice()
+                        .expect("patterns must be an array")
+                        .iter()
+                        .map(|p| compile_grok(&grok, p))
+                        .collect();
+                    let config = ParseConfig {
+                        patterns: patterns,
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                        tags: tags.clone(),
+                    };
+                    parse_filters.insert(config_path, config);
+                }
+                Some("json") => {
+                    let fields = tbl.lookup("fields").map(|fields| {
+                        fields.as_slice()
+                            .expect("fields must be an array")
+                            .iter()
+                            .map(|s| s.as_str().expect("field must be a string").to_string())
+                            .collect()
+                    });
+                    let config = JsonDecodeConfig {
+                        fields: fields,
+                        message_field: tbl.lookup("message_field").map(|s| {
+                            s.as_str().expect("message_field must be a string").to_string()
+                        }),
+                        timestamp_field: tbl.lookup("timestamp_field").map(|s| {
+                            s.as_str().expect("timestamp_field must be a string").to_string()
+                        }),
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                        tags: tags.clone(),
+                    };
+                    json_decode_filters.insert(config_path, config);
+                }
+                Some("log_metrics") => {
+                    let grok = parse_grok(tbl);
+                    let metrics = tbl.lookup("metrics")
+                        .expect("log_metrics filter must have metrics")
+                        .as_slice()
+                        .expect("metrics must be an array of tables")
+                        .iter()
+                        .map(|mtbl| {
+                            let value = mtbl.lookup("value").map(|v| {
+                                v.as_str().expect("value must be a string").to_string()
+                            });
+                            let aggregation = match mtbl.lookup("aggregation") {
+                                Some(aggr) => {
+                                    match aggr.as_str().expect("aggregation must be a string") {
+                                        "sum" => AggregationMethod::Sum,
+                                        "set" => AggregationMethod::Set,
+                                        "summarize" => AggregationMethod::Summarize,
+                                        other => panic!("unknown aggregation {}", other),
+                                    }
+                                }
+                                None if value.is_some() => AggregationMethod::Summarize,
+                                None => AggregationMethod::Sum,
+                            };
+                            LogMetric {
+                                name: mtbl.lookup("name")
+                                    .expect("log metric must have a name")
+                                    .as_str()
+                                    .expect("name must be a string")
+                                    .to_string(),
+                                pattern: mtbl.lookup("pattern").map(|p| compile_grok(&grok, p)),
+                                value: value,
+                                aggregation: aggregation,
+                                tags: match mtbl.lookup("tags") {
+                                    Some(tags) => {
+                                        tags.as_slice()
+                                            .expect("tags must be an array")
+                                            .iter()
+                                            .map(|t| {
+                                                t.as_str()
+                                                    .expect("tag must be a string")
+                                                    .to_string()
+                                            })
+                                            .collect()
+                                    }
+                                    None => Vec::new(),
+                                },
+                            }
+                        })
+                        .collect();
+                    let config = LogMetricsConfig {
+                        metrics: metrics,
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                        tags: tags.clone(),
+                    };
+                    log_metrics_filters.insert(config_path, config);
+                }
+                Some("rewrite") => {
+                    let rules = tbl.lookup("rules")
+                        .expect("rewrite filter must have rules")
+                        .as_slice()
+                        .expect("rules must be an array of tables")
+                        .iter()
+                        .map(parse_rewrite_rule)
+                        .collect();
+                    let config = RewriteConfig {
+                        rules: rules,
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                    };
+                    rewrite_filters.insert(config_path, config);
+                }
+                Some("router") => {
+                    let routes = tbl.lookup("routes")
+                        .expect("router filter must have routes")
+                        .as_slice()
+                        .expect("routes must be an array of tables")
+                        .iter()
+                        .map(|rtbl| {
+                            let kind = rtbl.lookup("kind").map(|k| {
+                                match k.as_str().expect("kind must be a string") {
+                                    "telemetry" => EventKind::Telemetry,
+                                    "log" => EventKind::Log,
+                                    other => panic!("unknown event kind {}", other),
+                                }
+                            });
+                            Route {
+                                kind: kind,
+                                name_prefix: rtbl.lookup("name_prefix").map(|p| {
+                                    p.as_str().expect("name_prefix must be a string").to_string()
+                                }),
+                                tags: string_table(rtbl, "tags"),
+                                forwards: string_array(rtbl, "forwards"),
+                            }
+                        })
+                        .collect();
+                    let config = RouterConfig {
+                        routes: routes,
+                        default: string_array(tbl, "default"),
+                        config_path: config_path.clone(),
+                    };
+                    router_filters.insert(config_path, config);
+                }
+                Some("throttle") => {
+                    let key = match tbl.lookup("key_tag") {
+                        Some(tag) => {
+                            ThrottleKey::Tag(tag.as_str()
+                                .expect("key_tag must be a string")
+                                .to_string())
+                        }
+                        None => ThrottleKey::Name,
+                    };
+                    let rate = number(tbl, "rate").expect("throttle filter must have a rate");
+                    let mode = match tbl.lookup("mode").map(|m| {
+                        m.as_str().expect("mode must be a string")
+                    }) {
+                        None | Some("drop") => ThrottleMode::Drop,
+                        Some("sample") => {
+                            let n = tbl.lookup("sample_rate")
+                                .expect("sample mode requires a sample_rate")
+                                .as_integer()
+                                .expect("sample_rate must be an integer");
+                            assert!(n > 0, "sample_rate must be positive");
+                            ThrottleMode::Sample(n as u64)
+                        }
+                        Some("summarize") => ThrottleMode::Summarize,
+                        Some(other) => panic!("unknown throttle mode {}", other),
+                    };
+                    let config = ThrottleConfig {
+                        key: key,
+                        rate: rate,
+                        burst: number(tbl, "burst").unwrap_or(rate),
+                        mode: mode,
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                        tags: tags.clone(),
+                    };
+                    throttle_filters.insert(config_path, config);
+                }
+                Some("dedup") => {
+                    let capacity = tbl.lookup("capacity")
+                        .unwrap_or(&Value::Integer(10_000))
+                        .as_integer()
+                        .expect("capacity must be an integer");
+                    assert!(capacity > 0, "capacity must be positive");
+                    let config = DedupConfig {
+                        mask: tbl.lookup("mask")
+                            .map_or(false, |m| m.as_bool().expect("mask must be a boolean")),
+                        capacity: capacity as usize,
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                    };
+                    dedup_filters.insert(config_path, config);
+                }
+                Some("cardinality") => {
+                    let limit = tbl.lookup("limit")
+                        .expect("cardinality filter must have a limit")
+                        .as_integer()
+                        .expect("limit must be an integer");
+                    assert!(limit > 0, "limit must be positive");
+                    let action = match tbl.lookup("action").map(|a| {
+                        a.as_str().expect("action must be a string")
+                    }) {
+                        None | Some("overflow") => CardinalityAction::Overflow,
+                        Some("drop_tag") => CardinalityAction::DropTag,
+                        Some("drop_series") => CardinalityAction::DropSeries,
+                        Some(other) => panic!("unknown cardinality action {}", other),
+                    };
+                    let max_names = tbl.lookup("max_names")
+                        .unwrap_or(&Value::Integer(10_000))
+                        .as_integer()
+                        .expect("max_names must be an integer");
+                    assert!(max_names > 0, "max_names must be positive");
+                    let config = CardinalityLimitConfig {
+                        limit: limit as usize,
+                        max_names: max_names as usize,
+                        action: action,
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                        tags: tags.clone(),
+                    };
+                    cardinality_filters.insert(config_path, config);
+                }
+                Some("aggregate") => {
+                    let config = AggregateConfig {
+                        bin_width: tbl.lookup("bin_width")
+                            .unwrap_or(&Value::Integer(1))
+                            .as_integer()
+                            .expect("bin_width must be an integer"),
+                        drop_tags: string_array(tbl, "drop_tags"),
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                    };
+                    aggregate_filters.insert(config_path, config);
+                }
+                Some("derivative") => {
+                    let mode = match tbl.lookup("mode").map(|m| {
+                        m.as_str().expect("mode must be a string")
+                    }) {
+                        None | Some("rate") => DerivativeMode::Rate,
+                        Some("delta") => DerivativeMode::Delta,
+                        Some(other) => panic!("unknown derivative mode {}", other),
+                    };
+                    let suffix = match tbl.lookup("suffix") {
+                        Some(suffix) => {
+                            suffix.as_str().expect("suffix must be a string").to_string()
                         }
-                        None => Vec::new(),
+                        None if mode == DerivativeMode::Rate => ".rate".to_string(),
+                        None => ".delta".to_string(),
                     };
-                    let config_path = format!("filters.{}", name);
-                    let config = ProgrammableFilterConfig {
-                        script: scripts_dir.join(path),
+                    let config = DerivativeConfig {
+                        name: tbl.lookup("name").map(|glob| {
+                            let glob = glob.as_str().expect("name must be a string");
+                            Pattern::new(glob)
+                                .expect(&format!("could not compile name glob {}", glob))
+                        }),
+                        mode: mode,
+                        suffix: suffix,
+                        keep_original: tbl.lookup("keep_original").map_or(false, |k| {
+                            k.as_bool().expect("keep_original must be a boolean")
+                        }),
+                        expire_after: tbl.lookup("expire_after").map_or(10, |e| {
+                            let e = e.as_integer().expect("expire_after must be an integer");
+                            assert!(e > 0, "expire_after must be positive");
+                            e as u64
+                        }),
+                        forwards: fwds,
+                        config_path: config_path.clone(),
+                    };
+                    derivative_filters.insert(config_path, config);
+                }
+                Some("wasm") => {
+                    let module = tbl.lookup("module")
+                        .expect("wasm filter must have a module")
+                        .as_str()
+                        .expect("module must be a string");
+                    let config = WasmFilterConfig {
+                        module: scripts_dir.join(module),
+                        on_error: parse_on_error(tbl),
+                        data_directory: data_directory.clone(),
                         forwards: fwds,
                         config_path: config_path.clone(),
                         tags: tags.clone(),
+                        instruction_limit: tbl.lookup("instruction_limit").and_then(|l| {
+                            match l.as_integer().expect("instruction_limit must be an integer") {
+                                0 => None,
+                                l => Some(l as u32),
+                            }
+                        }),
                     };
-                    filters.insert(config_path, config);
+                    wasm_filters.insert(config_path, config);
                 }
-                None => continue,
+                Some(other) => panic!("unknown filter type {} for {}", other, config_path),
             }
         }
     }
 
-
     let mut firehosen: Vec<FirehoseConfig> = Vec::new();
     match value.lookup("firehose") {
         Some(array) => {
@@ -620,11 +1180,7 @@ pub fn parse_config_file(buffer: String, verbosity: u64) -> Args {
     };
 
     Args {
-        data_directory: value.lookup("data-directory")
-            .unwrap_or(&Value::String("/tmp/cernan-data".to_string()))
-            .as_str()
-            .map(|s| Path::new(s).to_path_buf())
-            .unwrap(),
+        data_directory: data_directory,
         scripts_directory: scripts_dir,
         statsds: statsds,
         graphites: graphites,
@@ -642,6 +1198,17 @@ pub fn parse_config_file(buffer: String, verbosity: u64) -> Args {
         firehosen: firehosen,
         files: files,
         filters: filters,
+        parse_filters: parse_filters,
+        json_decode_filters: json_decode_filters,
+        log_metrics_filters: log_metrics_filters,
+        rewrite_filters: rewrite_filters,
+        router_filters: router_filters,
+        throttle_filters: throttle_filters,
+        dedup_filters: dedup_filters,
+        cardinality_filters: cardinality_filters,
+        aggregate_filters: aggregate_filters,
+        derivative_filters: derivative_filters,
+        wasm_filters: wasm_filters,
         verbose: verbosity,
         version: VERSION.unwrap().to_string(),
     }
@@ -649,8 +1216,12 @@ pub fn parse_config_file(buffer: String, verbosity: u64) -> Args {
 
 #[cfg(test)]
 mod test {
-    use filter::ProgrammableFilterConfig;
-    use metric::TagMap;
+    use filter::{AggregateConfig, CardinalityAction, CardinalityLimitConfig, DedupConfig,
+                 DerivativeConfig, DerivativeMode, ErrorPolicy, EventKind, JsonDecodeConfig,
+                 LogMetricsConfig, NameMatch, ParseConfig, ProgrammableFilterConfig, RewriteAction,
+                 RewriteConfig, RouterConfig, ScriptArg, ThrottleConfig, ThrottleKey,
+                 ThrottleMode, WasmFilterConfig};
+    use metric::{AggregationMethod, TagMap};
     use rusoto::Region;
     use std::path::{Path, PathBuf};
     use super::*;
@@ -965,7 +1536,139 @@ port = 1024
             args.filters.get("filters.collectd_scrub").unwrap();
         assert_eq!(config0.script.to_str().unwrap(),
                    "/tmp/cernan-scripts/cernan_bridge.lua");
+        assert_eq!(config0.on_error, ErrorPolicy::PassThrough);
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+    }
+
+    #[test]
+    fn config_filters_on_error() {
+        let config = r#"
+[filters]
+  [filters.collectd_scrub]
+  script = "cernan_bridge.lua"
+  on_error = "drop"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        let config0: &ProgrammableFilterConfig =
+            args.filters.get("filters.collectd_scrub").unwrap();
+        assert_eq!(config0.on_error, ErrorPolicy::Drop);
+    }
+
+    #[test]
+    fn config_filters_sandbox() {
+        let config = r#"
+[filters]
+  [filters.unrestricted]
+  script = "cernan_bridge.lua"
+
+  [filters.limited]
+  script = "cernan_bridge.lua"
+  sandbox = true
+  instruction_limit = 1000
+  memory_limit = 1048576
+
+  [filters.unlimited]
+  script = "cernan_bridge.lua"
+  sandbox = true
+  instruction_limit = 0
+  batch_size = 64
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        let config0: &ProgrammableFilterConfig =
+            args.filters.get("filters.unrestricted").unwrap();
+        assert!(!config0.sandbox);
+        assert_eq!(config0.instruction_limit, None);
+        assert_eq!(config0.memory_limit, None);
+
+        let config1: &ProgrammableFilterConfig = args.filters.get("filters.limited").unwrap();
+        assert!(config1.sandbox);
+        assert_eq!(config1.instruction_limit, Some(1000));
+        assert_eq!(config1.memory_limit, Some(1048576));
+
+        let config2: &ProgrammableFilterConfig = args.filters.get("filters.unlimited").unwrap();
+        assert!(config2.sandbox);
+        assert_eq!(config2.instruction_limit, None);
+        assert_eq!(config0.batch_size, 1);
+        assert_eq!(config2.batch_size, 64);
+        assert_eq!(config0.data_directory, Path::new("/tmp/cernan-data").to_path_buf());
+    }
+
+    #[test]
+    fn config_filters_wasm() {
+        let config = r#"
+scripts-directory = "/foo/bar"
+data-directory = "/var/lib/cernan"
+
+[filters]
+  [filters.scrub]
+  type = "wasm"
+  module = "scrub.wasm"
+  on_error = "drop"
+  forwards = ["sinks.console"]
+
+  [filters.limited]
+  type = "wasm"
+  module = "scrub.wasm"
+  instruction_limit = 1000
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert!(args.filters.is_empty());
+        let config0: &WasmFilterConfig = args.wasm_filters.get("filters.scrub").unwrap();
+        assert_eq!(config0.module, Path::new("/foo/bar/scrub.wasm"));
+        assert_eq!(config0.on_error, ErrorPolicy::Drop);
+        assert_eq!(config0.data_directory, Path::new("/var/lib/cernan"));
         assert_eq!(config0.forwards, vec!["sinks.console"]);
+        assert_eq!(config0.config_path, "filters.scrub");
+        assert_eq!(config0.instruction_limit, None);
+
+        let config1: &WasmFilterConfig = args.wasm_filters.get("filters.limited").unwrap();
+        assert_eq!(config1.instruction_limit, Some(1000));
+    }
+
+    #[test]
+    fn config_filters_shared_script_args() {
+        let config = r#"
+scripts-directory = "/foo/bar"
+
+[filters]
+  [filters.web_threshold]
+  script = "threshold.lua"
+  [filters.web_threshold.args]
+  threshold = 10.5
+  label = "web"
+
+  [filters.db_threshold]
+  script = "/opt/cernan/threshold.lua"
+  [filters.db_threshold.args]
+  threshold = 100
+  strict = true
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        let config0: &ProgrammableFilterConfig =
+            args.filters.get("filters.web_threshold").unwrap();
+        assert_eq!(config0.script, Path::new("/foo/bar/threshold.lua"));
+        assert_eq!(config0.args.get("threshold"), Some(&ScriptArg::Float(10.5)));
+        assert_eq!(config0.args.get("label"),
+                   Some(&ScriptArg::String("web".to_string())));
+
+        let config1: &ProgrammableFilterConfig =
+            args.filters.get("filters.db_threshold").unwrap();
+        assert_eq!(config1.script, Path::new("/opt/cernan/threshold.lua"));
+        assert_eq!(config1.args.get("threshold"), Some(&ScriptArg::Integer(100)));
+        assert_eq!(config1.args.get("strict"), Some(&ScriptArg::Boolean(true)));
+        assert_eq!(config1.args.get("label"), None);
     }
 
     #[test]
@@ -990,6 +1693,386 @@ scripts-directory = "data/"
         assert_eq!(config0.forwards, vec!["sinks.console"]);
     }
 
+    #[test]
+    fn config_filters_parse() {
+        let config = r#"
+[filters]
+  [filters.nginx]
+  type = "parse"
+  patterns = ["%{NGINXACCESS}", "^%{REQID:request_id} %{GREEDYDATA:message}"]
+  forwards = ["sinks.console"]
+    [filters.nginx.custom_patterns]
+    REQID = "req-[0-9a-f]+"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.filters.len(), 0);
+        assert_eq!(args.parse_filters.len(), 1);
+
+        let config0: &ParseConfig = args.parse_filters.get("filters.nginx").unwrap();
+        assert_eq!(config0.patterns.len(), 2);
+        assert!(config0.patterns[1].is_match("req-1f3 handled"));
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+    }
+
+    #[test]
+    fn config_filters_json() {
+        let config = r#"
+[filters]
+  [filters.structured]
+  type = "json"
+  fields = ["level", "service"]
+  message_field = "msg"
+  timestamp_field = "ts"
+  forwards = ["sinks.console"]
+
+  [filters.everything]
+  type = "json"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.json_decode_filters.len(), 2);
+
+        let config0: &JsonDecodeConfig =
+            args.json_decode_filters.get("filters.structured").unwrap();
+        assert_eq!(config0.fields,
+                   Some(vec!["level".to_string(), "service".to_string()]));
+        assert_eq!(config0.message_field, Some("msg".to_string()));
+        assert_eq!(config0.timestamp_field, Some("ts".to_string()));
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+
+        let config1: &JsonDecodeConfig =
+            args.json_decode_filters.get("filters.everything").unwrap();
+        assert_eq!(config1.fields, None);
+        assert_eq!(config1.message_field, None);
+        assert_eq!(config1.timestamp_field, None);
+    }
+
+    #[test]
+    fn config_filters_log_metrics() {
+        let config = r#"
+[filters]
+  [filters.access_metrics]
+  type = "log_metrics"
+  forwards = ["sinks.console"]
+
+    [[filters.access_metrics.metrics]]
+    name = "nginx.requests"
+    pattern = "%{NGINXACCESS}"
+    tags = ["response", "verb"]
+
+    [[filters.access_metrics.metrics]]
+    name = "nginx.request_time"
+    pattern = "%{NGINXACCESS}"
+    value = "request_time"
+
+    [[filters.access_metrics.metrics]]
+    name = "app.queue_depth"
+    value = "depth"
+    aggregation = "set"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.log_metrics_filters.len(), 1);
+
+        let config0: &LogMetricsConfig =
+            args.log_metrics_filters.get("filters.access_metrics").unwrap();
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+        assert_eq!(config0.metrics.len(), 3);
+
+        assert_eq!(config0.metrics[0].name, "nginx.requests");
+        assert!(config0.metrics[0].pattern.is_some());
+        assert_eq!(config0.metrics[0].value, None);
+        assert_eq!(config0.metrics[0].aggregation, AggregationMethod::Sum);
+        assert_eq!(config0.metrics[0].tags,
+                   vec!["response".to_string(), "verb".to_string()]);
+
+        assert_eq!(config0.metrics[1].value, Some("request_time".to_string()));
+        assert_eq!(config0.metrics[1].aggregation, AggregationMethod::Summarize);
+
+        assert!(config0.metrics[2].pattern.is_none());
+        assert_eq!(config0.metrics[2].aggregation, AggregationMethod::Set);
+    }
+
+    #[test]
+    fn config_filters_rewrite() {
+        let config = r#"
+[filters]
+  [filters.tidy]
+  type = "rewrite"
+  forwards = ["sinks.console"]
+
+    [[filters.tidy.rules]]
+    name_regex = "^collectd\\.([^.]+)\\.(.*)$"
+    rename = "collectd.$2"
+    set_tags = { source = "collectd" }
+
+    [[filters.tidy.rules]]
+    name = "app.*"
+    tags = { env = "prod" }
+    has_tags = ["hostname"]
+    rename_tags = { hostname = "host" }
+    remove_tags = ["env"]
+
+    [[filters.tidy.rules]]
+    tags = { level = "debug" }
+    drop = true
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.rewrite_filters.len(), 1);
+        let config0: &RewriteConfig = args.rewrite_filters.get("filters.tidy").unwrap();
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+        assert_eq!(config0.rules.len(), 3);
+
+        match config0.rules[0].name {
+            Some(NameMatch::Regex(ref re)) => assert!(re.is_match("collectd.web-1.cpu")),
+            _ => panic!("expected a regex"),
+        }
+        assert_eq!(config0.rules[0].actions,
+                   vec![RewriteAction::RenameMetric("collectd.$2".to_string()),
+                        RewriteAction::SetTag("source".to_string(), "collectd".to_string())]);
+
+        match config0.rules[1].name {
+            Some(NameMatch::Glob(ref glob)) => assert!(glob.matches("app.requests")),
+            _ => panic!("expected a glob"),
+        }
+        assert_eq!(config0.rules[1].tags,
+                   vec![("env".to_string(), Some("prod".to_string())),
+                        ("hostname".to_string(), None)]);
+        assert_eq!(config0.rules[1].actions,
+                   vec![RewriteAction::RenameTag("hostname".to_string(), "host".to_string()),
+                        RewriteAction::RemoveTag("env".to_string())]);
+
+        assert!(config0.rules[2].name.is_none());
+        assert_eq!(config0.rules[2].actions, vec![RewriteAction::Drop]);
+    }
+
+    #[test]
+    fn config_filters_router() {
+        let config = r#"
+[filters]
+  [filters.split]
+  type = "router"
+  default = ["sinks.console"]
+
+    [[filters.split.routes]]
+    name_prefix = "app."
+    forwards = ["sinks.wavefront"]
+
+    [[filters.split.routes]]
+    kind = "log"
+    tags = { level = "error" }
+    forwards = ["sinks.firehose.errors", "sinks.console"]
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.router_filters.len(), 1);
+        let config0: &RouterConfig = args.router_filters.get("filters.split").unwrap();
+        assert_eq!(config0.default, vec!["sinks.console"]);
+        assert_eq!(config0.routes.len(), 2);
+
+        assert_eq!(config0.routes[0].kind, None);
+        assert_eq!(config0.routes[0].name_prefix, Some("app.".to_string()));
+        assert!(config0.routes[0].tags.is_empty());
+        assert_eq!(config0.routes[0].forwards, vec!["sinks.wavefront"]);
+
+        assert_eq!(config0.routes[1].kind, Some(EventKind::Log));
+        assert_eq!(config0.routes[1].name_prefix, None);
+        assert_eq!(config0.routes[1].tags,
+                   vec![("level".to_string(), "error".to_string())]);
+        assert_eq!(config0.routes[1].forwards,
+                   vec!["sinks.firehose.errors", "sinks.console"]);
+    }
+
+    #[test]
+    fn config_filters_throttle() {
+        let config = r#"
+[filters]
+  [filters.per_service]
+  type = "throttle"
+  key_tag = "service"
+  rate = 100
+  burst = 500
+  mode = "summarize"
+  forwards = ["sinks.console"]
+
+  [filters.per_path]
+  type = "throttle"
+  rate = 0.5
+  mode = "sample"
+  sample_rate = 10
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.throttle_filters.len(), 2);
+
+        let config0: &ThrottleConfig = args.throttle_filters.get("filters.per_service").unwrap();
+        assert_eq!(config0.key, ThrottleKey::Tag("service".to_string()));
+        assert_eq!(config0.rate, 100.0);
+        assert_eq!(config0.burst, 500.0);
+        assert_eq!(config0.mode, ThrottleMode::Summarize);
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+
+        let config1: &ThrottleConfig = args.throttle_filters.get("filters.per_path").unwrap();
+        assert_eq!(config1.key, ThrottleKey::Name);
+        assert_eq!(config1.rate, 0.5);
+        assert_eq!(config1.burst, 0.5);
+        assert_eq!(config1.mode, ThrottleMode::Sample(10));
+    }
+
+    #[test]
+    fn config_filters_dedup() {
+        let config = r#"
+[filters]
+  [filters.crash_loop]
+  type = "dedup"
+  mask = true
+  capacity = 500
+  forwards = ["sinks.console"]
+
+  [filters.defaults]
+  type = "dedup"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.dedup_filters.len(), 2);
+
+        let config0: &DedupConfig = args.dedup_filters.get("filters.crash_loop").unwrap();
+        assert!(config0.mask);
+        assert_eq!(config0.capacity, 500);
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+
+        let config1: &DedupConfig = args.dedup_filters.get("filters.defaults").unwrap();
+        assert!(!config1.mask);
+        assert_eq!(config1.capacity, 10_000);
+    }
+
+    #[test]
+    fn config_filters_cardinality() {
+        let config = r#"
+[filters]
+  [filters.limit_tags]
+  type = "cardinality"
+  limit = 1000
+  action = "drop_tag"
+  max_names = 500
+  forwards = ["sinks.console"]
+
+  [filters.limit_default]
+  type = "cardinality"
+  limit = 50
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.cardinality_filters.len(), 2);
+
+        let config0: &CardinalityLimitConfig =
+            args.cardinality_filters.get("filters.limit_tags").unwrap();
+        assert_eq!(config0.limit, 1000);
+        assert_eq!(config0.action, CardinalityAction::DropTag);
+        assert_eq!(config0.max_names, 500);
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+
+        let config1: &CardinalityLimitConfig =
+            args.cardinality_filters.get("filters.limit_default").unwrap();
+        assert_eq!(config1.limit, 50);
+        assert_eq!(config1.max_names, 10_000);
+        assert_eq!(config1.action, CardinalityAction::Overflow);
+    }
+
+    #[test]
+    fn config_filters_aggregate() {
+        let config = r#"
+[filters]
+  [filters.rollup]
+  type = "aggregate"
+  bin_width = 10
+  drop_tags = ["host"]
+  forwards = ["sinks.console"]
+
+  [filters.defaults]
+  type = "aggregate"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.aggregate_filters.len(), 2);
+
+        let config0: &AggregateConfig = args.aggregate_filters.get("filters.rollup").unwrap();
+        assert_eq!(config0.bin_width, 10);
+        assert_eq!(config0.drop_tags, vec!["host"]);
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+
+        let config1: &AggregateConfig = args.aggregate_filters.get("filters.defaults").unwrap();
+        assert_eq!(config1.bin_width, 1);
+        assert!(config1.drop_tags.is_empty());
+    }
+
+    #[test]
+    fn config_filters_derivative() {
+        let config = r#"
+[filters]
+  [filters.collectd_rates]
+  type = "derivative"
+  name = "collectd.*"
+  keep_original = true
+  forwards = ["sinks.console"]
+
+  [filters.deltas]
+  type = "derivative"
+  mode = "delta"
+  expire_after = 3
+
+  [filters.per_second]
+  type = "derivative"
+  suffix = ".per_second"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.derivative_filters.len(), 3);
+
+        let config0: &DerivativeConfig =
+            args.derivative_filters.get("filters.collectd_rates").unwrap();
+        assert!(config0.name.as_ref().unwrap().matches("collectd.if.octets"));
+        assert_eq!(config0.mode, DerivativeMode::Rate);
+        assert_eq!(config0.suffix, ".rate");
+        assert!(config0.keep_original);
+        assert_eq!(config0.expire_after, 10);
+        assert_eq!(config0.forwards, vec!["sinks.console"]);
+
+        let config1: &DerivativeConfig = args.derivative_filters.get("filters.deltas").unwrap();
+        assert!(config1.name.is_none());
+        assert_eq!(config1.mode, DerivativeMode::Delta);
+        assert_eq!(config1.suffix, ".delta");
+        assert!(!config1.keep_original);
+        assert_eq!(config1.expire_after, 3);
+
+        let config2: &DerivativeConfig =
+            args.derivative_filters.get("filters.per_second").unwrap();
+        assert_eq!(config2.mode, DerivativeMode::Rate);
+        assert_eq!(config2.suffix, ".per_second");
+    }
+
     #[test]
     fn config_file_wavefront() {
         let config = r#"
@@ -1327,6 +2410,113 @@ path = "/bar.txt"
         assert_eq!(args.files[0].forwards, vec!["sink.bar.blech"]);
     }
 
+    #[test]
+    fn config_file_file_source_start_position() {
+        let config = r#"
+data-directory = "/foo/data"
+
+[sources]
+  [sources.files]
+  [sources.files.foo_bar_txt]
+  path = "/foo/bar.txt"
+  start_position = "checkpoint"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert!(!args.files.is_empty());
+        assert_eq!(args.files[0].start_position, StartPosition::Checkpoint);
+        assert_eq!(args.files[0].data_directory, PathBuf::from("/foo/data"));
+    }
+
+    #[test]
+    fn config_file_file_source_start_position_default() {
+        let config = r#"
+[[file]]
+path = "/foo/bar.txt"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert!(!args.files.is_empty());
+        assert_eq!(args.files[0].start_position, StartPosition::End);
+    }
+
+    #[test]
+    fn config_file_file_source_multiline() {
+        let config = r#"
+[sources]
+  [sources.files]
+  [sources.files.app_log]
+  path = "/foo/app.log"
+    [sources.files.app_log.multiline]
+    start_pattern = "^\\d{4}-\\d{2}-\\d{2}"
+    flush_timeout_ms = 250
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert!(!args.files.is_empty());
+        let multiline = args.files[0].multiline.as_ref().unwrap();
+        assert!(multiline.start_pattern.as_ref().unwrap().is_match("2017-01-01 boom"));
+        assert!(multiline.continuation_pattern.is_none());
+        assert_eq!(multiline.flush_timeout, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn config_file_file_source_open_files() {
+        let config = r#"
+[[file]]
+path = "/foo/*.log"
+max_open_files = 16
+idle_timeout_secs = 300
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert!(!args.files.is_empty());
+        assert_eq!(args.files[0].max_open_files, 16);
+        assert_eq!(args.files[0].idle_timeout, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn config_file_file_source_open_files_default() {
+        let config = r#"
+[[file]]
+path = "/foo/*.log"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert!(!args.files.is_empty());
+        assert_eq!(args.files[0].max_open_files, 1024);
+        assert_eq!(args.files[0].idle_timeout, None);
+    }
+
+    #[test]
+    fn config_file_file_source_encoding() {
+        let config = r#"
+[[file]]
+path = "/foo/bar.txt"
+encoding = "latin1"
+
+[[file]]
+path = "/foo/baz.txt"
+"#
+            .to_string();
+
+        let args = parse_config_file(config, 4);
+
+        assert_eq!(args.files.len(), 2);
+        assert_eq!(args.files[0].encoding, Encoding::Latin1);
+        assert_eq!(args.files[1].encoding, Encoding::Utf8Lossy);
+    }
+
     #[test]
     fn config_file_tags() {
         let config = r#"
@@ -0,0 +1,126 @@
+use buckets::Buckets;
+use filter;
+use metric;
+use std::sync;
+
+/// Pre-aggregate telemetry between flushes.
+///
+/// Telemetry is stored in `Buckets` with the configured `bin_width` and
+/// emitted, merged, on every flush. Points are merged according to their
+/// aggregation method, so summaries emitted from this filter carry the
+/// merged CKMS of all their points. Tags named in `drop_tags` are removed
+/// before a point is stored, rolling up series that differ only in those
+/// tags. Logs have nothing to merge and are forwarded as they arrive.
+pub struct Aggregate {
+    aggrs: Buckets,
+    drop_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregateConfig {
+    pub bin_width: i64,
+    pub drop_tags: Vec<String>,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+}
+
+impl Aggregate {
+    pub fn new(config: AggregateConfig) -> Aggregate {
+        Aggregate {
+            aggrs: Buckets::new(config.bin_width),
+            drop_tags: config.drop_tags,
+        }
+    }
+}
+
+impl filter::Filter for Aggregate {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Telemetry(mut m) => {
+                let mut telem = sync::Arc::make_mut(&mut m).take().unwrap();
+                if !self.drop_tags.is_empty() {
+                    let tags = sync::Arc::make_mut(&mut telem.tags);
+                    for key in &self.drop_tags {
+                        tags.remove(key);
+                    }
+                }
+                self.aggrs.add(telem);
+            }
+            metric::Event::TimerFlush => {
+                for values in &self.aggrs {
+                    for telem in values {
+                        res.push(metric::Event::new_telemetry(telem.clone()));
+                    }
+                }
+                self.aggrs.reset();
+            }
+            metric::Event::Log(_) => res.push(event),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use filter::test_support::{flush, log, telemetry};
+    use metric;
+    use super::*;
+
+    fn aggregate(drop_tags: Vec<&str>) -> Aggregate {
+        Aggregate::new(AggregateConfig {
+            bin_width: 10,
+            drop_tags: drop_tags.iter().map(|t| t.to_string()).collect(),
+            forwards: Vec::new(),
+            config_path: "filters.aggregate".to_string(),
+        })
+    }
+
+    #[test]
+    fn rolls_up_across_dropped_tags() {
+        let mut filter = aggregate(vec!["host"]);
+        let mut events = Vec::new();
+        for &(host, value) in &[("web-1", 1.0), ("web-2", 2.0), ("web-3", 3.0)] {
+            let telem = metric::Telemetry::new("app.requests", value)
+                .aggr_sum()
+                .timestamp(100)
+                .overlay_tag("host", host)
+                .overlay_tag("env", "prod");
+            filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+        }
+        for &(host, value) in &[("web-1", 0.1), ("web-2", 0.2), ("web-3", 0.3)] {
+            let telem = metric::Telemetry::new("app.latency", value)
+                .aggr_summarize()
+                .timestamp(100)
+                .overlay_tag("host", host);
+            filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+        }
+        assert!(events.is_empty());
+
+        let telems = telemetry(&flush(&mut filter));
+        assert_eq!(telems.len(), 2);
+
+        let latency = telems.iter().find(|t| t.name == "app.latency").unwrap();
+        assert_eq!(latency.count(), 3);
+        assert_eq!(latency.query(1.0), Some(0.3));
+        assert!(latency.tags.is_empty());
+
+        let requests = telems.iter().find(|t| t.name == "app.requests").unwrap();
+        assert_eq!(requests.value(), Some(6.0));
+        assert_eq!(requests.tags.get(&"host".to_string()), None);
+        assert_eq!(requests.tags.get(&"env".to_string()), Some(&"prod".to_string()));
+
+        assert!(flush(&mut filter).is_empty());
+    }
+
+    #[test]
+    fn logs_pass_through() {
+        let mut filter = aggregate(vec![]);
+        let mut events = Vec::new();
+        filter.process(log("app.log", "hello"), &mut events).unwrap();
+        assert_eq!(events.len(), 1);
+    }
+}
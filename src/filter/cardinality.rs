@@ -0,0 +1,364 @@
+use filter;
+use metric;
+use seahash::SeaHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hasher;
+use std::sync;
+
+/// Limit the number of distinct tag sets per metric name.
+///
+/// The first `limit` tag sets seen for a metric name are let through
+/// unchanged, as is any later telemetry with one of those tag sets. Telemetry
+/// with a new tag set beyond the limit has `action` taken on it:
+///
+///  * `DropTag`: offending tags are removed.
+///  * `Overflow`: offending tags' values are replaced with `__overflow__`.
+///  * `DropSeries`: the telemetry is dropped.
+///
+/// The first offending tag is the one with the most distinct values seen for
+/// the metric name. Further tags with more than `limit` distinct values are
+/// rewritten in turn until the tag set is one already seen. A rewritten tag
+/// set that is new is let through only if fewer than `limit` have been, so
+/// at most twice `limit` tag sets reach the filter's forwards per metric
+/// name. Anything else is dropped.
+///
+/// Limited points are counted per metric name and sent on at each flush as
+/// `cernan.cardinality.limited`, tagged with `metric_name` and `action`.
+/// Logs are forwarded as they arrive.
+///
+/// Up to `max_names` metric names are remembered, the one seen least recently
+/// making way for a new one. Each has at most twice `limit` tag sets and
+/// `limit + 1` values per tag key.
+pub struct CardinalityLimit {
+    limit: usize,
+    max_names: usize,
+    action: CardinalityAction,
+    tags: metric::TagMap,
+    series: HashMap<String, Series>,
+    limited: HashMap<String, u64>,
+    /// Counts admitted telemetry, ordering series by when they were last seen
+    clock: u64,
+    /// Metric names by when they were last seen, oldest first
+    recency: BTreeMap<u64, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CardinalityAction {
+    DropTag,
+    Overflow,
+    DropSeries,
+}
+
+#[derive(Debug, Clone)]
+pub struct CardinalityLimitConfig {
+    pub limit: usize,
+    /// The number of metric names whose tag sets are remembered
+    pub max_names: usize,
+    pub action: CardinalityAction,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub tags: metric::TagMap,
+}
+
+pub const OVERFLOW: &'static str = "__overflow__";
+
+#[derive(Default)]
+struct Series {
+    tag_sets: HashSet<u64>,
+    rewritten: HashSet<u64>,
+    values: HashMap<String, HashSet<u64>>,
+    last_seen: u64,
+}
+
+fn hash_str(s: &str) -> u64 {
+    let mut hasher = SeaHasher::default();
+    hasher.write(s.as_bytes());
+    hasher.finish()
+}
+
+fn hash_tags(tags: &metric::TagMap) -> u64 {
+    let mut hasher = SeaHasher::default();
+    for &(ref k, ref v) in tags.iter() {
+        hasher.write(k.as_bytes());
+        hasher.write_u8(0);
+        hasher.write(v.as_bytes());
+        hasher.write_u8(0);
+    }
+    hasher.finish()
+}
+
+impl Series {
+    fn observe(&mut self, tags: &metric::TagMap, limit: usize) {
+        for &(ref k, ref v) in tags.iter() {
+            let values = self.values.entry(k.clone()).or_insert_with(HashSet::new);
+            if values.len() <= limit {
+                values.insert(hash_str(v));
+            }
+        }
+    }
+
+    /// The tag in `tags` with the most distinct values, if it has more than
+    /// `min` and is not in `skip`
+    fn offending_tag(&self,
+                     tags: &metric::TagMap,
+                     skip: &HashSet<String>,
+                     min: usize)
+                     -> Option<String> {
+        let mut worst: Option<(&String, usize)> = None;
+        for &(ref k, _) in tags.iter() {
+            if skip.contains(k) {
+                continue;
+            }
+            let distinct = self.values.get(k).map_or(0, |v| v.len());
+            if distinct > min && worst.map_or(true, |(_, d)| distinct > d) {
+                worst = Some((k, distinct));
+            }
+        }
+        worst.map(|(k, _)| k.clone())
+    }
+
+    fn is_known(&self, tag_set: u64) -> bool {
+        self.tag_sets.contains(&tag_set) || self.rewritten.contains(&tag_set)
+    }
+}
+
+impl CardinalityLimit {
+    pub fn new(config: CardinalityLimitConfig) -> CardinalityLimit {
+        CardinalityLimit {
+            limit: config.limit,
+            max_names: config.max_names,
+            action: config.action,
+            tags: config.tags,
+            series: HashMap::new(),
+            limited: HashMap::new(),
+            clock: 0,
+            recency: BTreeMap::new(),
+        }
+    }
+
+    /// Forget the metric name seen least recently
+    fn evict(&mut self) {
+        let oldest = self.recency.keys().next().cloned();
+        if let Some(oldest) = oldest {
+            let name = self.recency.remove(&oldest).unwrap();
+            self.series.remove(&name);
+        }
+    }
+
+    /// Admit `telem`, adjusting its tags if need be. Returns false if the
+    /// telemetry is to be dropped.
+    fn admit(&mut self, telem: &mut metric::Telemetry) -> bool {
+        if !self.series.contains_key(&telem.name) && self.series.len() >= self.max_names {
+            self.evict();
+        }
+        let limit = self.limit;
+        self.clock += 1;
+        let series = self.series.entry(telem.name.clone()).or_insert_with(Series::default);
+        self.recency.remove(&series.last_seen);
+        self.recency.insert(self.clock, telem.name.clone());
+        series.last_seen = self.clock;
+        series.observe(&telem.tags, limit);
+
+        let tag_set = hash_tags(&telem.tags);
+        if series.is_known(tag_set) {
+            return true;
+        }
+        if series.tag_sets.len() < limit {
+            series.tag_sets.insert(tag_set);
+            return true;
+        }
+
+        *self.limited.entry(telem.name.clone()).or_insert(0) += 1;
+        if self.action == CardinalityAction::DropSeries {
+            return false;
+        }
+        let mut done = HashSet::new();
+        let mut min = 0;
+        while let Some(key) = series.offending_tag(&telem.tags, &done, min) {
+            {
+                let tags = sync::Arc::make_mut(&mut telem.tags);
+                if self.action == CardinalityAction::Overflow {
+                    tags.insert(key.clone(), OVERFLOW.to_string());
+                } else {
+                    tags.remove(&key);
+                }
+            }
+            if series.is_known(hash_tags(&telem.tags)) {
+                return true;
+            }
+            done.insert(key);
+            min = limit;
+        }
+        if done.is_empty() || series.rewritten.len() >= limit {
+            return false;
+        }
+        series.rewritten.insert(hash_tags(&telem.tags));
+        true
+    }
+}
+
+impl filter::Filter for CardinalityLimit {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Telemetry(mut m) => {
+                let mut telem = sync::Arc::make_mut(&mut m).take().unwrap();
+                if self.admit(&mut telem) {
+                    res.push(metric::Event::new_telemetry(telem));
+                }
+            }
+            metric::Event::TimerFlush => {
+                let action = match self.action {
+                    CardinalityAction::DropTag => "drop_tag",
+                    CardinalityAction::Overflow => "overflow",
+                    CardinalityAction::DropSeries => "drop_series",
+                };
+                for (name, count) in self.limited.drain() {
+                    let telem = metric::Telemetry::new("cernan.cardinality.limited",
+                                                       count as f64)
+                        .aggr_sum()
+                        .overlay_tags_from_map(&self.tags)
+                        .overlay_tag("metric_name", name.as_str())
+                        .overlay_tag("action", action);
+                    res.push(metric::Event::new_telemetry(telem));
+                }
+            }
+            metric::Event::Log(_) => res.push(event),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use filter::test_support::{flush, tag, telemetry};
+    use metric;
+    use super::*;
+
+    fn cardinality_limit(limit: usize, action: CardinalityAction) -> CardinalityLimit {
+        CardinalityLimit::new(CardinalityLimitConfig {
+            limit: limit,
+            max_names: 10,
+            action: action,
+            forwards: Vec::new(),
+            config_path: "filters.cardinality".to_string(),
+            tags: metric::TagMap::default(),
+        })
+    }
+
+    fn request(request_id: &str) -> metric::Event {
+        let telem = metric::Telemetry::new("app.requests", 1.0)
+            .overlay_tag("host", "web-1")
+            .overlay_tag("request_id", request_id);
+        metric::Event::new_telemetry(telem)
+    }
+
+    #[test]
+    fn drop_series() {
+        let mut filter = cardinality_limit(2, CardinalityAction::DropSeries);
+        let mut events = Vec::new();
+        for id in &["a", "b", "c", "d", "a"] {
+            filter.process(request(id), &mut events).unwrap();
+        }
+        assert_eq!(events.len(), 3);
+        assert_eq!(tag(&events[2], "request_id"), Some("a".to_string()));
+
+        let events = flush(&mut filter);
+        let telems = telemetry(&events);
+        assert_eq!(telems.len(), 1);
+        assert_eq!(telems[0].name, "cernan.cardinality.limited");
+        assert_eq!(telems[0].value(), Some(2.0));
+        assert_eq!(tag(&events[0], "metric_name"), Some("app.requests".to_string()));
+        assert_eq!(tag(&events[0], "action"), Some("drop_series".to_string()));
+    }
+
+    #[test]
+    fn overflow_offending_tag() {
+        let mut filter = cardinality_limit(2, CardinalityAction::Overflow);
+        let mut events = Vec::new();
+        for id in &["a", "b", "c", "d"] {
+            filter.process(request(id), &mut events).unwrap();
+        }
+        assert_eq!(events.len(), 4);
+        assert_eq!(tag(&events[1], "request_id"), Some("b".to_string()));
+        assert_eq!(tag(&events[2], "request_id"), Some(OVERFLOW.to_string()));
+        assert_eq!(tag(&events[3], "request_id"), Some(OVERFLOW.to_string()));
+        assert_eq!(tag(&events[3], "host"), Some("web-1".to_string()));
+    }
+
+    #[test]
+    fn overflow_two_exploding_tags() {
+        let mut filter = cardinality_limit(2, CardinalityAction::Overflow);
+        let mut events = Vec::new();
+        for id in &["a", "b", "c", "d", "e", "f"] {
+            let telem = metric::Telemetry::new("app.requests", 1.0)
+                .overlay_tag("host", "web-1")
+                .overlay_tag("request_id", id)
+                .overlay_tag("session_id", id);
+            filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+        }
+        // every tag set past the limit collapses into one
+        assert_eq!(events.len(), 6);
+        for event in &events[2..] {
+            assert_eq!(tag(event, "request_id"), Some(OVERFLOW.to_string()));
+            assert_eq!(tag(event, "session_id"), Some(OVERFLOW.to_string()));
+            assert_eq!(tag(event, "host"), Some("web-1".to_string()));
+        }
+        let series = filter.series.get("app.requests").unwrap();
+        assert_eq!(series.tag_sets.len() + series.rewritten.len(), 3);
+    }
+
+    #[test]
+    fn rewritten_tag_sets_are_limited() {
+        let mut filter = cardinality_limit(1, CardinalityAction::DropTag);
+        let mut events = Vec::new();
+        for &(id, status) in &[("a", "200"), ("b", "200"), ("c", "500"), ("d", "404")] {
+            let telem = metric::Telemetry::new("app.requests", 1.0)
+                .overlay_tag("request_id", id)
+                .overlay_tag("status", status);
+            filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+        }
+        // only one rewritten tag set is let through, the rest are dropped
+        assert_eq!(events.len(), 2);
+        assert_eq!(tag(&events[1], "request_id"), None);
+        assert_eq!(tag(&events[1], "status"), Some("200".to_string()));
+    }
+
+    #[test]
+    fn remembers_at_most_max_names() {
+        let mut filter = cardinality_limit(1, CardinalityAction::DropSeries);
+        let mut events = Vec::new();
+        for i in 0..25 {
+            let telem = metric::Telemetry::new(format!("app.metric{}", i), 1.0);
+            filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+            // seen throughout, so never the least recently seen
+            let telem = metric::Telemetry::new("app.metric0", 1.0);
+            filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+        }
+        assert_eq!(filter.series.len(), 10);
+        assert_eq!(filter.recency.len(), 10);
+        assert!(filter.series.contains_key("app.metric24"));
+        assert!(filter.series.contains_key("app.metric0"));
+        assert!(!filter.series.contains_key("app.metric1"));
+    }
+
+    #[test]
+    fn drop_offending_tag() {
+        let mut filter = cardinality_limit(1, CardinalityAction::DropTag);
+        let mut events = Vec::new();
+        for id in &["a", "b"] {
+            filter.process(request(id), &mut events).unwrap();
+        }
+        assert_eq!(events.len(), 2);
+        assert_eq!(tag(&events[1], "request_id"), None);
+        assert_eq!(tag(&events[1], "host"), Some("web-1".to_string()));
+
+        // other metric names have limits of their own
+        let other = metric::Telemetry::new("app.errors", 1.0).overlay_tag("request_id", "z");
+        filter.process(metric::Event::new_telemetry(other), &mut events).unwrap();
+        assert_eq!(tag(&events[2], "request_id"), Some("z".to_string()));
+    }
+}
@@ -0,0 +1,224 @@
+use filter;
+use metric;
+use regex::Regex;
+use seahash;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync;
+
+lazy_static! {
+    static ref UUID: Regex =
+        Regex::new("[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}")
+            .unwrap();
+    static ref DIGITS: Regex = Regex::new("[0-9]+").unwrap();
+}
+
+/// Collapse repeated log lines.
+///
+/// Log lines are held until the next flush. Lines with the same path and
+/// value seen in that time are collapsed into the first of them, which is
+/// then emitted with a `repeat_count` tag giving the number of lines it
+/// stands for. A line that was seen only once is emitted unchanged. With
+/// `mask` set, UUIDs and runs of digits are disregarded when comparing
+/// values, so lines differing only in ids, timestamps or counters collapse
+/// together.
+///
+/// Lines are compared by seahash fingerprint and at most `capacity` distinct
+/// lines are held. When a new line would exceed that the least recently seen
+/// line is emitted early to make room. Telemetry is never held back.
+pub struct Dedup {
+    mask: bool,
+    capacity: usize,
+    seq: u64,
+    lines: HashMap<u64, Pending>,
+    recency: BTreeMap<u64, u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    pub mask: bool,
+    pub capacity: usize,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+}
+
+struct Pending {
+    line: metric::LogLine,
+    count: u64,
+    first_seen: u64,
+    last_seen: u64,
+}
+
+impl Pending {
+    fn into_event(self) -> metric::Event {
+        let mut line = self.line;
+        if self.count > 1 {
+            line.tags.insert("repeat_count".to_string(), self.count.to_string());
+        }
+        metric::Event::new_log(line)
+    }
+}
+
+impl Dedup {
+    pub fn new(config: DedupConfig) -> Dedup {
+        Dedup {
+            mask: config.mask,
+            capacity: config.capacity,
+            seq: 0,
+            lines: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    fn fingerprint(&self, line: &metric::LogLine) -> u64 {
+        let value = if self.mask {
+            let value = UUID.replace_all(&line.value, "#");
+            Cow::Owned(DIGITS.replace_all(&value, "#").into_owned())
+        } else {
+            Cow::Borrowed(line.value.as_str())
+        };
+        let mut buf = Vec::with_capacity(line.path.len() + 1 + value.len());
+        buf.extend_from_slice(line.path.as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(value.as_bytes());
+        seahash::hash(&buf)
+    }
+
+    fn push(&mut self, line: metric::LogLine, res: &mut Vec<metric::Event>) {
+        let fp = self.fingerprint(&line);
+        self.seq += 1;
+        let seq = self.seq;
+        if let Some(pending) = self.lines.get_mut(&fp) {
+            self.recency.remove(&pending.last_seen);
+            self.recency.insert(seq, fp);
+            pending.count += 1;
+            pending.last_seen = seq;
+            return;
+        }
+
+        if self.lines.len() >= self.capacity {
+            let oldest = self.recency.keys().next().cloned();
+            if let Some(oldest) = oldest {
+                let evicted = self.recency.remove(&oldest).unwrap();
+                let pending = self.lines.remove(&evicted).unwrap();
+                res.push(pending.into_event());
+            }
+        }
+        self.recency.insert(seq, fp);
+        self.lines.insert(fp,
+                          Pending {
+                              line: line,
+                              count: 1,
+                              first_seen: seq,
+                              last_seen: seq,
+                          });
+    }
+
+    fn flush(&mut self, res: &mut Vec<metric::Event>) {
+        let mut pending: Vec<Pending> = self.lines.drain().map(|(_, p)| p).collect();
+        pending.sort_by_key(|p| p.first_seen);
+        for p in pending {
+            res.push(p.into_event());
+        }
+        self.recency.clear();
+    }
+}
+
+impl filter::Filter for Dedup {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Log(mut l) => {
+                let line = sync::Arc::make_mut(&mut l).take().unwrap();
+                self.push(line, res);
+            }
+            metric::Event::TimerFlush => self.flush(res),
+            metric::Event::Telemetry(_) => res.push(event),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use filter::test_support::{flush, log, logs};
+    use metric;
+    use super::*;
+
+    fn dedup(mask: bool, capacity: usize) -> Dedup {
+        Dedup::new(DedupConfig {
+            mask: mask,
+            capacity: capacity,
+            forwards: Vec::new(),
+            config_path: "filters.dedup".to_string(),
+        })
+    }
+
+    fn lines(events: &[metric::Event]) -> Vec<(String, Option<String>)> {
+        logs(events)
+            .into_iter()
+            .map(|l| {
+                let count = l.tags.get(&"repeat_count".to_string()).cloned();
+                (l.value, count)
+            })
+            .collect()
+    }
+
+    fn send(filter: &mut Dedup, value: &str, res: &mut Vec<metric::Event>) {
+        filter.process(log("app.log", value), res).unwrap();
+    }
+
+    #[test]
+    fn collapses_until_flush() {
+        let mut filter = dedup(false, 16);
+        let mut events = Vec::new();
+        send(&mut filter, "panic: out of memory", &mut events);
+        send(&mut filter, "restarting", &mut events);
+        send(&mut filter, "panic: out of memory", &mut events);
+        send(&mut filter, "panic: out of memory", &mut events);
+        assert!(events.is_empty());
+
+        assert_eq!(lines(&flush(&mut filter)),
+                   vec![("panic: out of memory".to_string(), Some("3".to_string())),
+                        ("restarting".to_string(), None)]);
+
+        assert!(flush(&mut filter).is_empty());
+    }
+
+    #[test]
+    fn masks_digits_and_uuids() {
+        let mut unmasked = dedup(false, 16);
+        let mut masked = dedup(true, 16);
+        let values = ["request 1b4e28ba-2fa1-11d2-883f-0016d3cca427 failed after 12ms",
+                      "request 6fa459ea-ee8a-3ca4-894e-db77e160355e failed after 340ms"];
+        let mut unmasked_events = Vec::new();
+        let mut masked_events = Vec::new();
+        for value in &values {
+            send(&mut unmasked, value, &mut unmasked_events);
+            send(&mut masked, value, &mut masked_events);
+        }
+        unmasked.process(metric::Event::TimerFlush, &mut unmasked_events).unwrap();
+        masked.process(metric::Event::TimerFlush, &mut masked_events).unwrap();
+
+        assert_eq!(unmasked_events.len(), 2);
+        assert_eq!(lines(&masked_events),
+                   vec![(values[0].to_string(), Some("2".to_string()))]);
+    }
+
+    #[test]
+    fn evicts_least_recently_seen() {
+        let mut filter = dedup(false, 2);
+        let mut events = Vec::new();
+        send(&mut filter, "a", &mut events);
+        send(&mut filter, "b", &mut events);
+        send(&mut filter, "a", &mut events);
+        send(&mut filter, "c", &mut events);
+        assert_eq!(lines(&events), vec![("b".to_string(), None)]);
+
+        assert_eq!(lines(&flush(&mut filter)),
+                   vec![("a".to_string(), Some("2".to_string())), ("c".to_string(), None)]);
+    }
+}
@@ -0,0 +1,238 @@
+use filter;
+use glob::Pattern;
+use metric;
+use std::collections::HashMap;
+use std::sync;
+
+/// Turn cumulative counters into rates or deltas.
+///
+/// The previous value of every series, identified by name and tags, is
+/// remembered. Each new point of a series is compared with the last and the
+/// difference emitted as a `Set` telemetry named with `suffix` appended.
+/// With `DerivativeMode::Rate` the difference is divided by the seconds
+/// between the two points' timestamps. A value lower than the last is taken
+/// to mean the counter has been reset, in which case the new value itself is
+/// the difference. The first point of a series produces nothing, nor does a
+/// point with the same timestamp as the last.
+///
+/// Only telemetry with a name matching `name`, if given, is considered. The
+/// considered points are dropped unless `keep_original` is set, while other
+/// telemetry and logs are sent on as is.
+///
+/// A series not seen for `expire_after` flushes is forgotten, so that the
+/// series of departed hosts don't accumulate. Its next point is taken as its
+/// first.
+pub struct Derivative {
+    name: Option<Pattern>,
+    mode: DerivativeMode,
+    suffix: String,
+    keep_original: bool,
+    expire_after: u64,
+    flushes: u64,
+    /// The timestamp and value of each series' last point, and the flush
+    /// count when it arrived
+    previous: HashMap<(String, metric::TagMap), (i64, f64, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DerivativeMode {
+    Rate,
+    Delta,
+}
+
+#[derive(Debug, Clone)]
+pub struct DerivativeConfig {
+    pub name: Option<Pattern>,
+    pub mode: DerivativeMode,
+    pub suffix: String,
+    pub keep_original: bool,
+    /// The number of flushes a series may go unseen before it is forgotten
+    pub expire_after: u64,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+}
+
+impl Derivative {
+    pub fn new(config: DerivativeConfig) -> Derivative {
+        Derivative {
+            name: config.name,
+            mode: config.mode,
+            suffix: config.suffix,
+            keep_original: config.keep_original,
+            expire_after: config.expire_after,
+            flushes: 0,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Forget the series not seen for `expire_after` flushes
+    fn expire(&mut self) {
+        self.flushes += 1;
+        let oldest = self.flushes.saturating_sub(self.expire_after);
+        let expired: Vec<(String, metric::TagMap)> = self.previous
+            .iter()
+            .filter(|&(_, &(_, _, seen))| seen < oldest)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.previous.remove(&key);
+        }
+    }
+
+    fn derive(&mut self, telem: &metric::Telemetry) -> Option<metric::Telemetry> {
+        let value = match telem.value() {
+            Some(v) => v,
+            None => return None,
+        };
+        let key = (telem.name.clone(), (*telem.tags).clone());
+        let latest = (telem.timestamp, value, self.flushes);
+        let (prev_time, prev_value, _) = match self.previous.insert(key, latest) {
+            Some(prev) => prev,
+            None => return None,
+        };
+        let delta = if value < prev_value {
+            value
+        } else {
+            value - prev_value
+        };
+        let result = match self.mode {
+            DerivativeMode::Delta => delta,
+            DerivativeMode::Rate => {
+                let elapsed = telem.timestamp - prev_time;
+                if elapsed <= 0 {
+                    return None;
+                }
+                delta / (elapsed as f64)
+            }
+        };
+        Some(metric::Telemetry::new(format!("{}{}", telem.name, self.suffix), result)
+            .aggr_set()
+            .timestamp(telem.timestamp)
+            .overlay_tags_from_map(&telem.tags))
+    }
+}
+
+impl filter::Filter for Derivative {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Telemetry(mut m) => {
+                let telem = sync::Arc::make_mut(&mut m).take().unwrap();
+                let considered = match self.name {
+                    Some(ref pattern) => pattern.matches(&telem.name),
+                    None => true,
+                };
+                if !considered {
+                    res.push(metric::Event::new_telemetry(telem));
+                    return Ok(());
+                }
+                if let Some(derived) = self.derive(&telem) {
+                    res.push(metric::Event::new_telemetry(derived));
+                }
+                if self.keep_original {
+                    res.push(metric::Event::new_telemetry(telem));
+                }
+            }
+            metric::Event::Log(_) => res.push(event),
+            metric::Event::TimerFlush => self.expire(),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use filter::test_support::{flush, telemetry};
+    use glob::Pattern;
+    use metric;
+    use metric::AggregationMethod;
+    use super::*;
+
+    fn derivative(name: Option<&str>, mode: DerivativeMode, keep_original: bool) -> Derivative {
+        Derivative::new(DerivativeConfig {
+            name: name.map(|n| Pattern::new(n).unwrap()),
+            mode: mode,
+            suffix: ".rate".to_string(),
+            keep_original: keep_original,
+            expire_after: 2,
+            forwards: Vec::new(),
+            config_path: "filters.derivative".to_string(),
+        })
+    }
+
+    fn counter(name: &str, host: &str, time: i64, value: f64) -> metric::Event {
+        metric::Event::new_telemetry(metric::Telemetry::new(name, value)
+            .aggr_set()
+            .timestamp(time)
+            .overlay_tag("host", host))
+    }
+
+    #[test]
+    fn rate_per_series() {
+        let mut filter = derivative(None, DerivativeMode::Rate, false);
+        let mut events = Vec::new();
+        filter.process(counter("if.octets", "a", 100, 1000.0), &mut events).unwrap();
+        filter.process(counter("if.octets", "b", 100, 50.0), &mut events).unwrap();
+        assert!(events.is_empty());
+
+        filter.process(counter("if.octets", "a", 110, 1500.0), &mut events).unwrap();
+        filter.process(counter("if.octets", "b", 105, 60.0), &mut events).unwrap();
+        let telems = telemetry(&events);
+        assert_eq!(telems.len(), 2);
+        assert_eq!(telems[0].name, "if.octets.rate");
+        assert_eq!(telems[0].value(), Some(50.0));
+        assert_eq!(telems[0].aggr_method, AggregationMethod::Set);
+        assert_eq!(telems[0].timestamp, 110);
+        assert_eq!(telems[0].tags.get(&"host".to_string()), Some(&"a".to_string()));
+        assert_eq!(telems[1].value(), Some(2.0));
+        assert_eq!(telems[1].tags.get(&"host".to_string()), Some(&"b".to_string()));
+    }
+
+    #[test]
+    fn delta_with_counter_reset() {
+        let mut filter = derivative(None, DerivativeMode::Delta, false);
+        let mut events = Vec::new();
+        filter.process(counter("requests", "a", 100, 40.0), &mut events).unwrap();
+        filter.process(counter("requests", "a", 110, 45.0), &mut events).unwrap();
+        filter.process(counter("requests", "a", 120, 3.0), &mut events).unwrap();
+        // the same timestamp is fine for a delta
+        filter.process(counter("requests", "a", 120, 10.0), &mut events).unwrap();
+        let values: Vec<Option<f64>> = telemetry(&events).iter().map(|t| t.value()).collect();
+        assert_eq!(values, vec![Some(5.0), Some(3.0), Some(7.0)]);
+    }
+
+    #[test]
+    fn unseen_series_expire() {
+        let mut filter = derivative(None, DerivativeMode::Delta, false);
+        let mut events = Vec::new();
+        filter.process(counter("requests", "a", 100, 40.0), &mut events).unwrap();
+        filter.process(counter("requests", "b", 100, 40.0), &mut events).unwrap();
+        for _ in 0..2 {
+            assert!(flush(&mut filter).is_empty());
+            filter.process(counter("requests", "a", 110, 45.0), &mut events).unwrap();
+        }
+        assert_eq!(filter.previous.len(), 2);
+
+        // b has now gone unseen for three flushes
+        assert!(flush(&mut filter).is_empty());
+        assert_eq!(filter.previous.len(), 1);
+        events.clear();
+        filter.process(counter("requests", "b", 140, 50.0), &mut events).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn only_matching_names() {
+        let mut filter = derivative(Some("collectd.*"), DerivativeMode::Rate, true);
+        let mut events = Vec::new();
+        filter.process(counter("app.gauge", "a", 100, 1.0), &mut events).unwrap();
+        filter.process(counter("collectd.octets", "a", 100, 1.0), &mut events).unwrap();
+        filter.process(counter("collectd.octets", "a", 101, 2.0), &mut events).unwrap();
+        let names: Vec<String> = telemetry(&events).into_iter().map(|t| t.name).collect();
+        assert_eq!(names,
+                   vec!["app.gauge", "collectd.octets", "collectd.octets.rate", "collectd.octets"]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Grok-style patterns, after Logstash.
+//!
+//! A grok pattern is a regular expression which may refer to other named
+//! patterns as `%{NAME}`. Writing `%{NAME:field}` wraps the referenced pattern
+//! in a capture group called `field`. A type suffix, as in
+//! `%{NUMBER:bytes:int}`, is accepted for compatibility and ignored.
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+/// The built-in pattern library. Patterns lean on one another, so take care
+/// when adjusting any one of them.
+pub const GROK_PATTERNS: [(&'static str, &'static str); 46] =
+    [("USERNAME", r"[a-zA-Z0-9._-]+"),
+     ("USER", r"%{USERNAME}"),
+     ("INT", r"(?:[+-]?(?:[0-9]+))"),
+     ("BASE10NUM", r"(?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))"),
+     ("NUMBER", r"(?:%{BASE10NUM})"),
+     ("POSINT", r"\b(?:[1-9][0-9]*)\b"),
+     ("NONNEGINT", r"\b(?:[0-9]+)\b"),
+     ("WORD", r"\b\w+\b"),
+     ("NOTSPACE", r"\S+"),
+     ("SPACE", r"\s*"),
+     ("DATA", r".*?"),
+     ("GREEDYDATA", r".*"),
+     ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*""#),
+     ("QS", r"%{QUOTEDSTRING}"),
+     ("UUID", r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}"),
+     ("IPV4",
+      r"(?:(?:25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9])\.){3}(?:25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9])"),
+     ("IPV6", r"(?:[0-9A-Fa-f]{0,4}:){2,7}[0-9A-Fa-f]{0,4}"),
+     ("IP", r"(?:%{IPV6}|%{IPV4})"),
+     ("HOSTNAME",
+      r"\b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?"),
+     ("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+     ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+     ("UNIXPATH", r"(?:/[\w_%!$@:.,+~-]*)+"),
+     ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+     ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+     ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+     ("MONTH",
+      r"\b(?:Jan(?:uary)?|Feb(?:ruary)?|Mar(?:ch)?|Apr(?:il)?|May|Jun(?:e)?|Jul(?:y)?|Aug(?:ust)?|Sep(?:tember)?|Oct(?:ober)?|Nov(?:ember)?|Dec(?:ember)?)\b"),
+     ("MONTHNUM", r"(?:0?[1-9]|1[0-2])"),
+     ("MONTHDAY", r"(?:(?:0[1-9])|(?:[12][0-9])|(?:3[01])|[1-9])"),
+     ("YEAR", r"(?:\d\d){1,2}"),
+     ("HOUR", r"(?:2[0123]|[01]?[0-9])"),
+     ("MINUTE", r"(?:[0-5][0-9])"),
+     ("SECOND", r"(?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)"),
+     ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+     ("ISO8601_TIMEZONE", r"(?:Z|[+-]%{HOUR}(?::?%{MINUTE}))"),
+     ("TIMESTAMP_ISO8601",
+      r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?"),
+     ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+     ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+     ("PROG", r"[\x21-\x5a\x5c\x5e-\x7e]+"),
+     ("SYSLOGPROG", r"%{PROG:program}(?:\[%{POSINT:pid}\])?"),
+     ("SYSLOGHOST", r"%{IPORHOST}"),
+     ("SYSLOGBASE", r"%{SYSLOGTIMESTAMP:timestamp} %{SYSLOGHOST:logsource} %{SYSLOGPROG}:"),
+     ("SYSLOGLINE", r"%{SYSLOGBASE} %{GREEDYDATA:message}"),
+     ("COMMONAPACHELOG",
+      r#"%{IPORHOST:clientip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response} (?:%{NUMBER:bytes}|-)"#),
+     ("COMBINEDAPACHELOG", r"%{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}"),
+     ("NGINXACCESS",
+      r#"%{IPORHOST:clientip} - %{USER:remote_user} \[%{HTTPDATE:timestamp}\] "%{WORD:verb} %{NOTSPACE:request} HTTP/%{NUMBER:httpversion}" %{INT:response} %{INT:bytes} %{QS:referrer} %{QS:agent}(?: %{NUMBER:request_time})?"#),
+     ("LOGLEVEL",
+      r"(?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?)")];
+
+// Patterns may refer to one another but a cycle would never finish
+// expanding. No sensible pattern nests this deeply.
+const MAX_DEPTH: usize = 32;
+
+lazy_static! {
+    static ref REFERENCE: Regex =
+        Regex::new(r"%\{(?P<pattern>\w+)(?::(?P<field>\w+))?(?::\w+)?\}").unwrap();
+}
+
+/// A library of named grok patterns.
+pub struct Grok {
+    library: HashMap<String, String>,
+}
+
+impl Default for Grok {
+    /// Create a Grok with only the built-in patterns
+    fn default() -> Grok {
+        let mut library = HashMap::new();
+        for &(name, pattern) in GROK_PATTERNS.iter() {
+            library.insert(name.to_string(), pattern.to_string());
+        }
+        Grok { library: library }
+    }
+}
+
+impl Grok {
+    /// Add a named pattern, replacing any existing pattern of that name
+    pub fn insert<S>(&mut self, name: S, pattern: S)
+        where S: Into<String>
+    {
+        self.library.insert(name.into(), pattern.into());
+    }
+
+    /// Expand and compile a grok pattern into a regular expression
+    pub fn compile(&self, pattern: &str) -> Result<Regex, String> {
+        let expanded = self.expand(pattern, 0)?;
+        Regex::new(&expanded).map_err(|e| format!("{}", e))
+    }
+
+    fn expand(&self, pattern: &str, depth: usize) -> Result<String, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("grok pattern nested too deeply: {}", pattern));
+        }
+        let mut err = None;
+        let expanded = REFERENCE.replace_all(pattern, |caps: &Captures| {
+            let name = caps.name("pattern").unwrap().as_str();
+            let inner = match self.library.get(name) {
+                Some(inner) => {
+                    match self.expand(inner, depth + 1) {
+                        Ok(inner) => inner,
+                        Err(e) => {
+                            err = Some(e);
+                            return String::new();
+                        }
+                    }
+                }
+                None => {
+                    err = Some(format!("no such grok pattern: {}", name));
+                    return String::new();
+                }
+            };
+            match caps.name("field") {
+                Some(field) => format!("(?P<{}>{})", field.as_str(), inner),
+                None => format!("(?:{})", inner),
+            }
+        });
+        match err {
+            Some(e) => Err(e),
+            None => Ok(expanded.into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn capture(re: &Regex, line: &str, field: &str) -> Option<String> {
+        re.captures(line)
+            .and_then(|caps| caps.name(field).map(|m| m.as_str().to_string()))
+    }
+
+    #[test]
+    fn builtin_patterns_compile() {
+        let grok = Grok::default();
+        for &(name, _) in GROK_PATTERNS.iter() {
+            assert!(grok.compile(&format!("%{{{}}}", name)).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_pattern_is_an_error() {
+        let grok = Grok::default();
+        assert!(grok.compile("%{NOT_A_PATTERN:foo}").is_err());
+    }
+
+    #[test]
+    fn cyclic_pattern_is_an_error() {
+        let mut grok = Grok::default();
+        grok.insert("LOOP", "a%{LOOP}");
+        assert!(grok.compile("%{LOOP}").is_err());
+    }
+
+    #[test]
+    fn custom_pattern_with_field() {
+        let mut grok = Grok::default();
+        grok.insert("REQID", "req-[0-9a-f]+");
+        let re = grok.compile("handled %{REQID:request_id} in %{NUMBER:duration}ms").unwrap();
+        let line = "handled req-1f3 in 12.5ms";
+        assert_eq!(capture(&re, line, "request_id"), Some("req-1f3".to_string()));
+        assert_eq!(capture(&re, line, "duration"), Some("12.5".to_string()));
+    }
+
+    #[test]
+    fn apache_combined() {
+        let re = Grok::default().compile("%{COMBINEDAPACHELOG}").unwrap();
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08""#;
+        assert_eq!(capture(&re, line, "clientip"), Some("127.0.0.1".to_string()));
+        assert_eq!(capture(&re, line, "auth"), Some("frank".to_string()));
+        assert_eq!(capture(&re, line, "verb"), Some("GET".to_string()));
+        assert_eq!(capture(&re, line, "request"), Some("/apache_pb.gif".to_string()));
+        assert_eq!(capture(&re, line, "response"), Some("200".to_string()));
+        assert_eq!(capture(&re, line, "bytes"), Some("2326".to_string()));
+        assert_eq!(capture(&re, line, "agent"), Some(r#""Mozilla/4.08""#.to_string()));
+    }
+
+    #[test]
+    fn nginx_access() {
+        let re = Grok::default().compile("%{NGINXACCESS}").unwrap();
+        let line = r#"10.0.0.2 - - [01/Mar/2017:10:00:00 +0000] "POST /v1/orders HTTP/1.1" 201 512 "-" "curl/7.51.0" 0.042"#;
+        assert_eq!(capture(&re, line, "clientip"), Some("10.0.0.2".to_string()));
+        assert_eq!(capture(&re, line, "verb"), Some("POST".to_string()));
+        assert_eq!(capture(&re, line, "response"), Some("201".to_string()));
+        assert_eq!(capture(&re, line, "request_time"), Some("0.042".to_string()));
+    }
+
+    #[test]
+    fn syslog() {
+        let re = Grok::default().compile("%{SYSLOGLINE}").unwrap();
+        let line = "Mar  1 10:00:00 web-1 sshd[4242]: Accepted publickey for deploy";
+        assert_eq!(capture(&re, line, "logsource"), Some("web-1".to_string()));
+        assert_eq!(capture(&re, line, "program"), Some("sshd".to_string()));
+        assert_eq!(capture(&re, line, "pid"), Some("4242".to_string()));
+        assert_eq!(capture(&re, line, "message"),
+                   Some("Accepted publickey for deploy".to_string()));
+    }
+}
@@ -0,0 +1,200 @@
+use metric;
+use metric::{AggregationMethod, LogLine, TagMap, Telemetry};
+use protocols::graphite::parse_graphite;
+use protocols::statsd::parse_statsd;
+use serde_json;
+use serde_json::{Map, Value};
+use std::sync;
+
+/// The path given to log lines read by `parse_event` that do not set one
+pub const HARNESS_PATH: &'static str = "filter-test";
+
+fn json_tags(value: Option<&Value>) -> TagMap {
+    let mut tags = TagMap::default();
+    if let Some(&Value::Object(ref obj)) = value {
+        for (key, val) in obj {
+            let val = match *val {
+                Value::String(ref s) => s.clone(),
+                Value::I64(i) => i.to_string(),
+                Value::U64(u) => u.to_string(),
+                Value::F64(f) => f.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => continue,
+            };
+            tags.insert(key.clone(), val);
+        }
+    }
+    tags
+}
+
+fn json_event(obj: &Map<String, Value>) -> Option<metric::Event> {
+    if let Some(name) = obj.get("metric").and_then(|n| n.as_str()) {
+        let value = match obj.get("value").and_then(|v| v.as_f64()) {
+            Some(v) => v,
+            None => return None,
+        };
+        let mut metric = Telemetry::new(name, value)
+            .overlay_tags_from_map(&json_tags(obj.get("tags")));
+        metric = match obj.get("aggr").and_then(|a| a.as_str()) {
+            Some("set") => metric.aggr_set(),
+            Some("sum") => metric.aggr_sum(),
+            Some("summarize") => metric.aggr_summarize(),
+            _ => metric,
+        };
+        if let Some(ts) = obj.get("timestamp").and_then(|t| t.as_i64()) {
+            metric = metric.timestamp(ts);
+        }
+        if obj.get("persist").and_then(|p| p.as_bool()) == Some(true) {
+            metric = metric.persist();
+        }
+        Some(metric::Event::new_telemetry(metric))
+    } else if let Some(value) = obj.get("log").and_then(|l| l.as_str()) {
+        let path = obj.get("path").and_then(|p| p.as_str()).unwrap_or(HARNESS_PATH);
+        let mut log = LogLine::new(path, value).overlay_tags_from_map(&json_tags(obj.get("tags")));
+        if let Some(time) = obj.get("time").and_then(|t| t.as_i64()) {
+            log = log.time(time);
+        }
+        Some(metric::Event::new_log(log))
+    } else {
+        None
+    }
+}
+
+/// Parse one line of filter test input into events
+///
+/// A line may be a JSON object describing a single event, as produced by
+/// `render_event`, a statsd line or a graphite line. Anything else,
+/// including JSON objects with neither a `metric` nor a `log` key, becomes a
+/// log line with path `HARNESS_PATH`. Blank lines produce no events.
+pub fn parse_event(line: &str) -> Vec<metric::Event> {
+    let line = line.trim_right_matches(|c| c == '\n' || c == '\r');
+    if line.trim().is_empty() {
+        return Vec::new();
+    }
+    if line.starts_with('{') {
+        if let Ok(Value::Object(obj)) = serde_json::from_str::<Value>(line) {
+            if let Some(event) = json_event(&obj) {
+                return vec![event];
+            }
+        }
+    } else {
+        let basic_metric = sync::Arc::new(Some(Telemetry::default()));
+        let mut metrics = Vec::new();
+        if parse_statsd(line, &mut metrics, basic_metric.clone()) ||
+           parse_graphite(line, &mut metrics, basic_metric) {
+            return metrics.into_iter().map(metric::Event::new_telemetry).collect();
+        }
+    }
+    vec![metric::Event::new_log(LogLine::new(HARNESS_PATH, line))]
+}
+
+fn tags_json(tags: &TagMap) -> Value {
+    let mut obj = Map::new();
+    for &(ref key, ref val) in tags.iter() {
+        obj.insert(key.clone(), Value::String(val.clone()));
+    }
+    Value::Object(obj)
+}
+
+/// Render an event as a single line of JSON
+///
+/// The output is accepted by `parse_event`, so the results of one filter run
+/// can be fed to another or kept as the expected output of a test. Keys are
+/// sorted. `TimerFlush` has no rendering.
+pub fn render_event(event: &metric::Event) -> Option<String> {
+    let mut obj = Map::new();
+    match *event {
+        metric::Event::Telemetry(ref m) => {
+            let m = match **m {
+                Some(ref m) => m,
+                None => return None,
+            };
+            let aggr = match m.aggr_method {
+                AggregationMethod::Set => "set",
+                AggregationMethod::Sum => "sum",
+                AggregationMethod::Summarize => "summarize",
+            };
+            obj.insert("metric".to_string(), Value::String(m.name.clone()));
+            obj.insert("value".to_string(),
+                       m.value().map_or(Value::Null, Value::F64));
+            obj.insert("aggr".to_string(), Value::String(aggr.to_string()));
+            obj.insert("timestamp".to_string(), Value::I64(m.timestamp));
+            obj.insert("persist".to_string(), Value::Bool(m.persist));
+            obj.insert("tags".to_string(), tags_json(&m.tags));
+        }
+        metric::Event::Log(ref l) => {
+            let l = match **l {
+                Some(ref l) => l,
+                None => return None,
+            };
+            obj.insert("log".to_string(), Value::String(l.value.clone()));
+            obj.insert("path".to_string(), Value::String(l.path.clone()));
+            obj.insert("time".to_string(), Value::I64(l.time));
+            obj.insert("tags".to_string(), tags_json(&l.tags));
+        }
+        metric::Event::TimerFlush => return None,
+    }
+    serde_json::to_string(&Value::Object(obj)).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric;
+    use std::sync::Arc;
+
+    fn telemetry(mut event: metric::Event) -> metric::Telemetry {
+        match event {
+            metric::Event::Telemetry(ref mut m) => Arc::make_mut(m).take().unwrap(),
+            _ => panic!("not telemetry: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn parse_statsd_graphite_and_log_lines() {
+        let mut events = parse_event("foo.bar:12|c");
+        assert_eq!(events.len(), 1);
+        let m = telemetry(events.pop().unwrap());
+        assert_eq!(m.name, "foo.bar");
+        assert_eq!(m.value(), Some(12.0));
+
+        let mut events = parse_event("foo.baz 1.5 101\n");
+        assert_eq!(events.len(), 1);
+        let m = telemetry(events.pop().unwrap());
+        assert_eq!(m.name, "foo.baz");
+        assert_eq!(m.timestamp, 101);
+
+        let events = parse_event("GET /index.html 200");
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            metric::Event::Log(ref l) => {
+                let l = l.as_ref().as_ref().unwrap();
+                assert_eq!(l.path, HARNESS_PATH);
+                assert_eq!(l.value, "GET /index.html 200");
+            }
+            _ => unreachable!(),
+        }
+
+        assert!(parse_event("   ").is_empty());
+    }
+
+    #[test]
+    fn render_round_trips_through_parse() {
+        let metric = metric::Telemetry::new("requests", 4.0)
+            .aggr_sum()
+            .timestamp(10)
+            .overlay_tag("host", "web-1");
+        let log = metric::LogLine::new("/var/log/app", "hello")
+            .time(20)
+            .overlay_tag("level", "info");
+        for event in vec![metric::Event::new_telemetry(metric), metric::Event::new_log(log)] {
+            let line = render_event(&event).unwrap();
+            let mut parsed = parse_event(&line);
+            assert_eq!(parsed.len(), 1);
+            let parsed = parsed.pop().unwrap();
+            assert_eq!(render_event(&parsed), Some(line));
+            assert_eq!(parsed, event);
+        }
+        assert_eq!(render_event(&metric::Event::TimerFlush), None);
+    }
+}
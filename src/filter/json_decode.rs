@@ -0,0 +1,225 @@
+use chrono::DateTime;
+use filter;
+use metric;
+use serde_json;
+use serde_json::Value;
+use std::sync;
+
+/// Decode log lines which are single JSON objects.
+///
+/// The top-level keys of the object are lifted into the line's tags. If
+/// `fields` is configured only those keys are lifted, otherwise every key
+/// with a string, number or boolean value is. When `message_field` is set
+/// and present its content replaces the line's value. When
+/// `timestamp_field` is set and present it replaces the line's time. The
+/// timestamp may be given as seconds since the epoch or as an RFC 3339
+/// string.
+///
+/// Lines that are not JSON objects go on as they came. If there were any
+/// since the last flush, their number is sent as
+/// `cernan.filter.<config_path>.decode_failure` when the next comes. Only
+/// logs are decoded; telemetry is forwarded as received.
+pub struct JsonDecode {
+    fields: Option<Vec<String>>,
+    message_field: Option<String>,
+    timestamp_field: Option<String>,
+    config_path: String,
+    tags: metric::TagMap,
+    failures: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonDecodeConfig {
+    pub fields: Option<Vec<String>>,
+    pub message_field: Option<String>,
+    pub timestamp_field: Option<String>,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub tags: metric::TagMap,
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref s) => Some(s.clone()),
+        Value::I64(i) => Some(i.to_string()),
+        Value::U64(u) => Some(u.to_string()),
+        Value::F64(f) => Some(f.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn timestamp(value: &Value) -> Option<i64> {
+    match *value {
+        Value::I64(i) => Some(i),
+        Value::U64(u) => Some(u as i64),
+        Value::F64(f) => Some(f as i64),
+        Value::String(ref s) => DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.timestamp()),
+        _ => None,
+    }
+}
+
+impl JsonDecode {
+    pub fn new(config: JsonDecodeConfig) -> JsonDecode {
+        JsonDecode {
+            fields: config.fields,
+            message_field: config.message_field,
+            timestamp_field: config.timestamp_field,
+            config_path: config.config_path,
+            tags: config.tags,
+            failures: 0,
+        }
+    }
+
+    fn decode(&self, line: &mut metric::LogLine) -> bool {
+        let obj = match serde_json::from_str::<Value>(&line.value) {
+            Ok(Value::Object(obj)) => obj,
+            _ => return false,
+        };
+
+        match self.fields {
+            Some(ref fields) => {
+                for field in fields {
+                    if let Some(val) = obj.get(field).and_then(scalar) {
+                        line.tags.insert(field.clone(), val);
+                    }
+                }
+            }
+            None => {
+                for (key, val) in &obj {
+                    if Some(key) == self.message_field.as_ref() ||
+                       Some(key) == self.timestamp_field.as_ref() {
+                        continue;
+                    }
+                    if let Some(val) = scalar(val) {
+                        line.tags.insert(key.clone(), val);
+                    }
+                }
+            }
+        }
+        if let Some(ref field) = self.timestamp_field {
+            if let Some(time) = obj.get(field).and_then(timestamp) {
+                line.time = time;
+            }
+        }
+        if let Some(ref field) = self.message_field {
+            if let Some(msg) = obj.get(field).and_then(scalar) {
+                line.value = msg;
+            }
+        }
+        true
+    }
+}
+
+impl filter::Filter for JsonDecode {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Log(mut l) => {
+                let mut line = sync::Arc::make_mut(&mut l).take().unwrap();
+                if !self.decode(&mut line) {
+                    self.failures += 1;
+                }
+                res.push(metric::Event::new_log(line));
+            }
+            metric::Event::TimerFlush => {
+                if self.failures > 0 {
+                    let name = format!("cernan.filter.{}.decode_failure", self.config_path);
+                    let telem = metric::Telemetry::new(name, self.failures as f64)
+                        .aggr_sum()
+                        .overlay_tags_from_map(&self.tags);
+                    res.push(metric::Event::new_telemetry(telem));
+                    self.failures = 0;
+                }
+            }
+            metric::Event::Telemetry(_) => res.push(event),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use filter::test_support::{flush, logs, telemetry};
+    use metric;
+    use super::*;
+
+    fn json_decode(fields: Option<Vec<&str>>,
+                   message_field: Option<&str>,
+                   timestamp_field: Option<&str>)
+                   -> JsonDecode {
+        JsonDecode::new(JsonDecodeConfig {
+            fields: fields.map(|fs| fs.iter().map(|f| f.to_string()).collect()),
+            message_field: message_field.map(|f| f.to_string()),
+            timestamp_field: timestamp_field.map(|f| f.to_string()),
+            forwards: Vec::new(),
+            config_path: "filters.json".to_string(),
+            tags: metric::TagMap::default(),
+        })
+    }
+
+    fn decode(filter: &mut JsonDecode, value: &str) -> metric::LogLine {
+        let mut events = Vec::new();
+        let line = metric::LogLine::new("app.log", value).time(10);
+        filter.process(metric::Event::new_log(line), &mut events).unwrap();
+        assert_eq!(events.len(), 1);
+        logs(&events).pop().unwrap()
+    }
+
+    fn tag(line: &metric::LogLine, key: &str) -> Option<String> {
+        line.tags.get(&key.to_string()).cloned()
+    }
+
+    #[test]
+    fn lifts_all_scalars() {
+        let mut filter = json_decode(None, None, None);
+        let line = decode(&mut filter,
+                          r#"{"level":"info","status":200,"ok":true,"ctx":{"a":1},"xs":[1]}"#);
+        assert_eq!(tag(&line, "level"), Some("info".to_string()));
+        assert_eq!(tag(&line, "status"), Some("200".to_string()));
+        assert_eq!(tag(&line, "ok"), Some("true".to_string()));
+        assert_eq!(tag(&line, "ctx"), None);
+        assert_eq!(tag(&line, "xs"), None);
+        assert_eq!(line.time, 10);
+    }
+
+    #[test]
+    fn lifts_selected_fields() {
+        let mut filter = json_decode(Some(vec!["level", "missing"]), None, None);
+        let line = decode(&mut filter, r#"{"level":"warn","status":500}"#);
+        assert_eq!(tag(&line, "level"), Some("warn".to_string()));
+        assert_eq!(tag(&line, "status"), None);
+        assert_eq!(tag(&line, "missing"), None);
+    }
+
+    #[test]
+    fn message_and_timestamp() {
+        let mut filter = json_decode(None, Some("msg"), Some("ts"));
+        let line = decode(&mut filter,
+                          r#"{"msg":"user logged in","ts":"2017-03-01T10:00:00Z","user":"a"}"#);
+        assert_eq!(line.value, "user logged in");
+        assert_eq!(line.time, 1488362400);
+        assert_eq!(tag(&line, "user"), Some("a".to_string()));
+        assert_eq!(tag(&line, "msg"), None);
+
+        let line = decode(&mut filter, r#"{"msg":"epoch","ts":1488362401}"#);
+        assert_eq!(line.time, 1488362401);
+    }
+
+    #[test]
+    fn non_json_is_counted() {
+        let mut filter = json_decode(None, Some("msg"), None);
+        let line = decode(&mut filter, "plain text line");
+        assert_eq!(line.value, "plain text line");
+        let line = decode(&mut filter, "[1, 2, 3]");
+        assert_eq!(line.value, "[1, 2, 3]");
+
+        let telems = telemetry(&flush(&mut filter));
+        assert_eq!(telems.len(), 1);
+        assert_eq!(telems[0].name, "cernan.filter.filters.json.decode_failure");
+        assert_eq!(telems[0].value(), Some(2.0));
+    }
+}
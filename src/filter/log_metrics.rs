@@ -0,0 +1,206 @@
+use filter;
+use metric;
+use metric::AggregationMethod;
+use regex::{Captures, Regex};
+
+/// Derive telemetry from log lines.
+///
+/// Every configured `LogMetric` is checked against each `LogLine`. A metric
+/// with a `pattern` applies only to lines whose value matches; a metric
+/// without one applies to every line. The telemetry value is read from the
+/// `value` field, or is 1 when no field is named, which makes for a count of
+/// matching lines. Fields are looked up first among the pattern's named
+/// captures and then among the line's tags, so this filter composes with
+/// `Parse` and `JsonDecode` upstream. Each name in `tags` is looked up the
+/// same way and attached to the telemetry if found.
+///
+/// Log lines are passed on unchanged alongside any derived telemetry.
+pub struct LogMetrics {
+    metrics: Vec<LogMetric>,
+    tags: metric::TagMap,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogMetric {
+    pub name: String,
+    pub pattern: Option<Regex>,
+    pub value: Option<String>,
+    pub aggregation: AggregationMethod,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogMetricsConfig {
+    pub metrics: Vec<LogMetric>,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub tags: metric::TagMap,
+}
+
+fn field<'a>(caps: Option<&Captures<'a>>,
+             line: &'a metric::LogLine,
+             name: &str)
+             -> Option<&'a str> {
+    match caps.and_then(|c| c.name(name)) {
+        Some(m) => Some(m.as_str()),
+        None => line.tags.get(&name.to_string()).map(|v| v.as_str()),
+    }
+}
+
+impl LogMetrics {
+    pub fn new(config: LogMetricsConfig) -> LogMetrics {
+        LogMetrics {
+            metrics: config.metrics,
+            tags: config.tags,
+        }
+    }
+
+    fn derive(&self, line: &metric::LogLine, res: &mut Vec<metric::Event>) {
+        for lm in &self.metrics {
+            let caps = match lm.pattern {
+                Some(ref re) => {
+                    match re.captures(&line.value) {
+                        Some(caps) => Some(caps),
+                        None => continue,
+                    }
+                }
+                None => None,
+            };
+            let value = match lm.value {
+                Some(ref name) => {
+                    match field(caps.as_ref(), line, name).and_then(|v| v.parse::<f64>().ok()) {
+                        Some(value) => value,
+                        None => {
+                            trace!("no numeric field {} for {} in {:?}", name, lm.name, line);
+                            continue;
+                        }
+                    }
+                }
+                None => 1.0,
+            };
+            let mut telem = metric::Telemetry::new(lm.name.as_str(), value)
+                .timestamp(line.time)
+                .overlay_tags_from_map(&self.tags);
+            telem = match lm.aggregation {
+                AggregationMethod::Sum => telem.aggr_sum(),
+                AggregationMethod::Set => telem.aggr_set(),
+                AggregationMethod::Summarize => telem.aggr_summarize(),
+            };
+            for tag in &lm.tags {
+                if let Some(v) = field(caps.as_ref(), line, tag) {
+                    telem = telem.overlay_tag(tag.as_str(), v);
+                }
+            }
+            res.push(metric::Event::new_telemetry(telem));
+        }
+    }
+}
+
+impl filter::Filter for LogMetrics {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Log(l) => {
+                if let Some(ref line) = *l {
+                    self.derive(line, res);
+                }
+                res.push(metric::Event::Log(l));
+            }
+            metric::Event::Telemetry(_) => res.push(event),
+            metric::Event::TimerFlush => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::{Filter, Grok};
+    use filter::test_support::{log, telemetry};
+    use metric;
+    use metric::AggregationMethod;
+    use super::*;
+
+    fn log_metrics(metrics: Vec<LogMetric>) -> LogMetrics {
+        LogMetrics::new(LogMetricsConfig {
+            metrics: metrics,
+            forwards: Vec::new(),
+            config_path: "filters.log_metrics".to_string(),
+            tags: metric::TagMap::default(),
+        })
+    }
+
+    #[test]
+    fn counts_and_summarizes() {
+        let pattern = Grok::default().compile("%{NGINXACCESS}").unwrap();
+        let requests = LogMetric {
+            name: "requests".to_string(),
+            pattern: Some(pattern.clone()),
+            value: None,
+            aggregation: AggregationMethod::Sum,
+            tags: vec!["response".to_string()],
+        };
+        let request_time = LogMetric {
+            name: "request_time".to_string(),
+            pattern: Some(pattern),
+            value: Some("request_time".to_string()),
+            aggregation: AggregationMethod::Summarize,
+            tags: vec!["verb".to_string()],
+        };
+        let mut filter = log_metrics(vec![requests, request_time]);
+        let mut events = Vec::new();
+        let line = metric::LogLine::new("access.log",
+                                        "10.0.0.2 - - [01/Mar/2017:10:00:00 +0000] \"GET / \
+                                         HTTP/1.1\" 200 512 \"-\" \"curl/7.51.0\" 0.042")
+            .time(101);
+        filter.process(metric::Event::new_log(line), &mut events).unwrap();
+
+        // both telemetry and the original log line
+        assert_eq!(events.len(), 3);
+        let telems = telemetry(&events);
+        assert_eq!(telems.len(), 2);
+
+        assert_eq!(telems[0].name, "requests");
+        assert_eq!(telems[0].value(), Some(1.0));
+        assert_eq!(telems[0].aggr_method, AggregationMethod::Sum);
+        assert_eq!(telems[0].timestamp, 101);
+        assert_eq!(telems[0].tags.get(&"response".to_string()),
+                   Some(&"200".to_string()));
+
+        assert_eq!(telems[1].name, "request_time");
+        assert_eq!(telems[1].query(1.0), Some(0.042));
+        assert_eq!(telems[1].aggr_method, AggregationMethod::Summarize);
+        assert_eq!(telems[1].tags.get(&"verb".to_string()), Some(&"GET".to_string()));
+    }
+
+    #[test]
+    fn fields_fall_back_to_log_tags() {
+        let bytes = LogMetric {
+            name: "bytes".to_string(),
+            pattern: None,
+            value: Some("bytes".to_string()),
+            aggregation: AggregationMethod::Sum,
+            tags: vec!["service".to_string()],
+        };
+        let mut filter = log_metrics(vec![bytes]);
+        let mut events = Vec::new();
+        let line = metric::LogLine::new("app.log", "whatever")
+            .overlay_tag("bytes", "512")
+            .overlay_tag("service", "api");
+        filter.process(metric::Event::new_log(line), &mut events).unwrap();
+        let telems = telemetry(&events);
+        assert_eq!(telems.len(), 1);
+        assert_eq!(telems[0].value(), Some(512.0));
+        assert_eq!(telems[0].tags.get(&"service".to_string()), Some(&"api".to_string()));
+
+        // a missing or non-numeric value produces no telemetry
+        events.clear();
+        filter.process(log("app.log", "whatever"), &mut events).unwrap();
+        let line = metric::LogLine::new("app.log", "whatever").overlay_tag("bytes", "lots");
+        filter.process(metric::Event::new_log(line), &mut events).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(telemetry(&events).is_empty());
+    }
+}
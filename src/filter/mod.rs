@@ -1,26 +1,85 @@
 use hopper;
 use metric;
+use std::mem;
 use time;
 use util;
 
+mod aggregate;
+mod cardinality;
+mod dedup;
+mod derivative;
+mod grok;
+mod harness;
+mod json_decode;
+mod log_metrics;
+mod parse;
 mod programmable_filter;
+mod rewrite;
+mod router;
+mod state;
+#[cfg(test)]
+mod test_support;
+mod throttle;
+mod wasm_filter;
 
-pub use self::programmable_filter::{ProgrammableFilter, ProgrammableFilterConfig};
+pub use self::aggregate::{Aggregate, AggregateConfig};
+pub use self::cardinality::{CardinalityAction, CardinalityLimit, CardinalityLimitConfig,
+                             OVERFLOW};
+pub use self::dedup::{Dedup, DedupConfig};
+pub use self::derivative::{Derivative, DerivativeConfig, DerivativeMode};
+pub use self::grok::{GROK_PATTERNS, Grok};
+pub use self::harness::{HARNESS_PATH, parse_event, render_event};
+pub use self::json_decode::{JsonDecode, JsonDecodeConfig};
+pub use self::log_metrics::{LogMetric, LogMetrics, LogMetricsConfig};
+pub use self::parse::{Parse, ParseConfig};
+pub use self::programmable_filter::{ErrorPolicy, ProgrammableFilter, ProgrammableFilterConfig,
+                                    ScriptArg, reload_on_sighup};
+pub use self::rewrite::{NameMatch, Rewrite, RewriteAction, RewriteConfig, RewriteRule};
+pub use self::router::{EventKind, Route, Router, RouterConfig};
+pub use self::throttle::{Throttle, ThrottleConfig, ThrottleKey, ThrottleMode};
+#[cfg(feature = "wasm")]
+pub use self::wasm_filter::WasmFilter;
+pub use self::wasm_filter::WasmFilterConfig;
 
 #[derive(Debug)]
 pub enum FilterError {
     NoSuchFunction(&'static str, metric::Event),
+    /// A filter script raised an error. Carries the error message and the
+    /// events to send on in place of the script's output.
+    ScriptError(String, Vec<metric::Event>),
 }
 
-fn name_in_fe(fe: &FilterError) -> &'static str {
+fn name_in_fe(fe: &FilterError) -> &str {
     match *fe {
         FilterError::NoSuchFunction(n, _) => n,
+        FilterError::ScriptError(ref msg, _) => msg,
     }
 }
 
-fn event_in_fe(fe: FilterError) -> metric::Event {
+fn events_in_fe(fe: FilterError) -> Vec<metric::Event> {
     match fe {
-        FilterError::NoSuchFunction(_, m) => m,
+        FilterError::NoSuchFunction(_, m) => vec![m],
+        FilterError::ScriptError(_, ms) => ms,
+    }
+}
+
+/// Send the outcome of processing on to `chans`
+fn forward(result: Result<(), FilterError>,
+           events: &mut Vec<metric::Event>,
+           chans: &mut util::Channel) {
+    match result {
+        Ok(()) => {
+            for ev in events.drain(..) {
+                util::send("filter", chans, ev)
+            }
+        }
+        Err(fe) => {
+            events.clear();
+            error!("Failed to run filter with error: {:?}", name_in_fe(&fe));
+            for event in events_in_fe(fe) {
+                util::send("filter.error_path", chans, event);
+            }
+        }
     }
 }
 
@@ -29,25 +88,63 @@ pub trait Filter {
                event: metric::Event,
                res: &mut Vec<metric::Event>)
                -> Result<(), FilterError>;
+
+    /// The number of events `run` gathers before handing them to
+    /// `process_batch`. Filters that don't batch see one event at a time.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Process `events` one at a time. As when unbatched, a failure costs
+    /// only the event that caused it, whatever the error carries being sent
+    /// on in its place.
+    fn process_batch(&mut self,
+                     events: Vec<metric::Event>,
+                     res: &mut Vec<metric::Event>)
+                     -> Result<(), FilterError> {
+        for event in events {
+            if let Err(fe) = self.process(event, res) {
+                error!("Failed to run filter with error: {:?}", name_in_fe(&fe));
+                res.extend(events_in_fe(fe));
+            }
+        }
+        Ok(())
+    }
+
     fn run(&mut self, mut recv: hopper::Receiver<metric::Event>, mut chans: util::Channel) {
         let mut attempts = 0;
         let mut events = Vec::with_capacity(64);
+        let batch_size = self.batch_size();
+        let mut batch = Vec::with_capacity(batch_size);
         loop {
             time::delay(attempts);
             match recv.next() {
                 None => attempts += 1,
                 Some(event) => {
                     attempts = 0;
-                    match self.process(event, &mut events) {
-                        Ok(()) => {
-                            for ev in events.drain(..) {
-                                util::send("filter", &mut chans, ev)
-                            }
+                    if batch_size <= 1 {
+                        let result = self.process(event, &mut events);
+                        forward(result, &mut events, &mut chans);
+                        continue;
+                    }
+                    // Batches are handed over when full or, so that no event
+                    // is held past a flush, just ahead of a TimerFlush.
+                    match event {
+                        metric::Event::TimerFlush => {
+                            let full = mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                            let result = self.process_batch(full, &mut events);
+                            forward(result, &mut events, &mut chans);
+                            let result = self.process(metric::Event::TimerFlush, &mut events);
+                            forward(result, &mut events, &mut chans);
                         }
-                        Err(fe) => {
-                            error!("Failed to run filter with error: {:?}", name_in_fe(&fe));
-                            let event = event_in_fe(fe);
-                            util::send("filter.error_path", &mut chans, event);
+                        other => {
+                            batch.push(other);
+                            if batch.len() >= batch_size {
+                                let full = mem::replace(&mut batch,
+                                                        Vec::with_capacity(batch_size));
+                                let result = self.process_batch(full, &mut events);
+                                forward(result, &mut events, &mut chans);
+                            }
                         }
                     }
                 }
@@ -55,3 +152,37 @@ pub trait Filter {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use filter::test_support::log;
+    use metric;
+    use super::*;
+
+    /// Passes telemetry on, failing on anything else
+    struct TelemetryOnly;
+
+    impl Filter for TelemetryOnly {
+        fn process(&mut self,
+                   event: metric::Event,
+                   res: &mut Vec<metric::Event>)
+                   -> Result<(), FilterError> {
+            match event {
+                metric::Event::Telemetry(_) => {
+                    res.push(event);
+                    Ok(())
+                }
+                other => Err(FilterError::NoSuchFunction("process_log", other)),
+            }
+        }
+    }
+
+    #[test]
+    fn process_batch_isolates_failures() {
+        let metric = metric::Event::new_telemetry(metric::Telemetry::new("a", 1.0));
+        let batch = vec![metric.clone(), log("some.path", "b"), metric];
+        let mut res = Vec::new();
+        assert!(TelemetryOnly.process_batch(batch.clone(), &mut res).is_ok());
+        assert_eq!(res, batch);
+    }
+}
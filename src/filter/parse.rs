@@ -0,0 +1,140 @@
+use filter;
+use metric;
+use regex::Regex;
+use std::sync;
+
+/// Extract fields from log lines with grok patterns.
+///
+/// Each `LogLine` value is matched against the configured patterns in
+/// order. The named captures of the first pattern to match are written into
+/// the line's tags, overwriting any existing tag of the same name. A line no
+/// pattern matches keeps the tags it had, and the misses between two flushes
+/// are summed into `cernan.filter.<config_path>.no_match` at the second.
+/// Telemetry has no text to match and is sent straight on.
+pub struct Parse {
+    patterns: Vec<Regex>,
+    config_path: String,
+    tags: metric::TagMap,
+    no_match: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseConfig {
+    pub patterns: Vec<Regex>,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub tags: metric::TagMap,
+}
+
+impl Parse {
+    pub fn new(config: ParseConfig) -> Parse {
+        Parse {
+            patterns: config.patterns,
+            config_path: config.config_path,
+            tags: config.tags,
+            no_match: 0,
+        }
+    }
+
+    fn parse(&self, line: &mut metric::LogLine) -> bool {
+        for re in &self.patterns {
+            if let Some(caps) = re.captures(&line.value) {
+                for name in re.capture_names() {
+                    if let Some(name) = name {
+                        if let Some(m) = caps.name(name) {
+                            line.tags.insert(name.to_string(), m.as_str().to_string());
+                        }
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl filter::Filter for Parse {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Log(mut l) => {
+                let mut line = sync::Arc::make_mut(&mut l).take().unwrap();
+                if !self.parse(&mut line) {
+                    self.no_match += 1;
+                }
+                res.push(metric::Event::new_log(line));
+            }
+            metric::Event::TimerFlush => {
+                if self.no_match > 0 {
+                    let name = format!("cernan.filter.{}.no_match", self.config_path);
+                    let telem = metric::Telemetry::new(name, self.no_match as f64)
+                        .aggr_sum()
+                        .overlay_tags_from_map(&self.tags);
+                    res.push(metric::Event::new_telemetry(telem));
+                    self.no_match = 0;
+                }
+            }
+            metric::Event::Telemetry(_) => res.push(event),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::{Filter, Grok};
+    use filter::test_support::{flush, log, logs, tag, telemetry};
+    use metric;
+    use super::*;
+
+    fn parse(patterns: Vec<&str>) -> Parse {
+        let grok = Grok::default();
+        Parse::new(ParseConfig {
+            patterns: patterns.iter().map(|p| grok.compile(p).unwrap()).collect(),
+            forwards: Vec::new(),
+            config_path: "filters.parse".to_string(),
+            tags: metric::TagMap::default(),
+        })
+    }
+
+    #[test]
+    fn captures_become_tags() {
+        let mut filter = parse(vec!["%{WORD:level}: %{GREEDYDATA:message}"]);
+        let mut events = Vec::new();
+        filter.process(log("app.log", "ERROR: disk full"), &mut events).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(logs(&events)[0].value, "ERROR: disk full");
+        assert_eq!(tag(&events[0], "level"), Some("ERROR".to_string()));
+        assert_eq!(tag(&events[0], "message"), Some("disk full".to_string()));
+    }
+
+    #[test]
+    fn first_matching_pattern_wins() {
+        let mut filter = parse(vec!["^%{INT:code}$", "^%{WORD:word}$"]);
+        let mut events = Vec::new();
+        filter.process(log("app.log", "hello"), &mut events).unwrap();
+
+        assert_eq!(tag(&events[0], "code"), None);
+        assert_eq!(tag(&events[0], "word"), Some("hello".to_string()));
+    }
+
+    #[test]
+    fn non_matching_lines_are_counted() {
+        let mut filter = parse(vec!["^%{INT:code}$"]);
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            filter.process(log("app.log", "not a number"), &mut events).unwrap();
+        }
+        assert_eq!(events.len(), 3);
+
+        let telems = telemetry(&flush(&mut filter));
+        assert_eq!(telems.len(), 1);
+        assert_eq!(telems[0].name, "cernan.filter.filters.parse.no_match");
+        assert_eq!(telems[0].value(), Some(3.0));
+
+        assert!(flush(&mut filter).is_empty());
+    }
+}
@@ -1,64 +1,273 @@
 use filter;
-use libc::c_int;
+use filter::state::{StateStore, StateValue, state_path};
+use libc;
+use libc::{c_char, c_int, c_void, size_t};
 
 use lua;
-use lua::{Function, State, ThreadStatus};
+use lua::{Function, State, ThreadStatus, Type};
 use lua::ffi::lua_State;
 use metric;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::sync;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Bumped on every SIGHUP. Filters reload their script when they notice it
+/// has changed.
+static RELOAD_GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// How often, in seconds, a filter checks its script for modification
+const RELOAD_CHECK_INTERVAL: u64 = 1;
+
+/// The functions that handle events. A script must define at least one of
+/// them to be loaded, whether at startup or on reload.
+const ENTRY_POINTS: [&'static str; 3] = ["process_metric", "process_log", "process_batch"];
+
+extern "C" fn handle_sighup(_: c_int) {
+    RELOAD_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Ask all programmable filters to reload their scripts on SIGHUP
+pub fn reload_on_sighup() {
+    unsafe {
+        libc::signal(libc::SIGHUP, handle_sighup as libc::sighandler_t);
+    }
+}
 
 struct Payload<'a> {
-    metrics: Vec<Box<metric::Telemetry>>, // TODO if we switch from Box to Arc we
-    // might be better off
-    logs: Vec<Box<metric::LogLine>>,
+    // Events are shared with whoever else holds them, a pass-through copy
+    // say, until the script modifies them.
+    metrics: Vec<sync::Arc<Option<metric::Telemetry>>>,
+    logs: Vec<sync::Arc<Option<metric::LogLine>>>,
     global_tags: &'a metric::TagMap,
     path: &'a str,
+    store: &'a mut StateStore,
 }
 
-fn idx(n: i64, top: usize) -> usize {
-    if n < 0 {
-        (top - (n.abs() as usize))
+/// The position of the Lua index `n` among `top` items, counting back from
+/// the end if negative, or None if no item is there.
+fn position(n: i64, top: usize) -> Option<usize> {
+    let i = if n < 0 { top as i64 + n } else { n - 1 };
+    if i >= 0 && (i as usize) < top {
+        Some(i as usize)
     } else {
-        (n - 1) as usize
+        None
     }
 }
 
-impl<'a> Payload<'a> {
-    fn from_metric(m: metric::Telemetry, tags: &'a metric::TagMap, path: &'a str) -> Payload<'a> {
-        Payload {
-            metrics: vec![Box::new(m)],
-            logs: Vec::new(),
-            global_tags: tags,
-            path: path,
+/// The position of the index passed as argument 2 among `top` items. Raises
+/// a Lua argument error, failing the script, if no item is there.
+fn check_idx(state: &mut State, top: usize) -> usize {
+    match position(state.check_integer(2), top) {
+        Some(i) => i,
+        None => {
+            // State::arg_error would leak its message on every call.
+            let msg = b"index out of range\0";
+            unsafe {
+                lua::ffi::luaL_argerror(state.as_ptr(), 2, msg.as_ptr() as *const c_char);
+            }
+            unreachable!()
         }
     }
+}
 
-    fn from_log(l: metric::LogLine, tags: &'a metric::TagMap, path: &'a str) -> Payload<'a> {
-        Payload {
-            metrics: Vec::new(),
-            logs: vec![Box::new(l)],
-            global_tags: tags,
-            path: path,
+fn aggr_method(name: &str) -> Option<metric::AggregationMethod> {
+    match name {
+        "set" => Some(metric::AggregationMethod::Set),
+        "sum" => Some(metric::AggregationMethod::Sum),
+        "summarize" => Some(metric::AggregationMethod::Summarize),
+        _ => None,
+    }
+}
+
+/// The string, or number converted to one, at `index`. None for any other
+/// type, nil included, which `to_str` would render as "nil".
+fn string_at(state: &mut State, index: c_int) -> Option<String> {
+    if state.is_string(index) {
+        state.to_str_in_place(index).map(|s| s.to_owned())
+    } else {
+        None
+    }
+}
+
+/// Push a new table holding the key / value pairs of `tags`.
+fn push_tags(state: &mut State, tags: &metric::TagMap) {
+    state.new_table();
+    for &(ref key, ref val) in tags.iter() {
+        state.push_string(val);
+        state.set_field(-2, key);
+    }
+}
+
+/// Read the string keys and values of the table at `index` into a TagMap.
+/// Pairs with a non-string key or value are skipped.
+fn table_to_tags(state: &mut State, index: c_int) -> metric::TagMap {
+    let mut tags = metric::TagMap::default();
+    state.push_nil();
+    while state.next(index) {
+        // to_str_in_place converts a number where it sits, which would
+        // confuse next were the key one. The value is popped regardless.
+        if state.type_of(-2) == Some(Type::String) && state.is_string(-1) {
+            let key = state.to_str_in_place(-2).map(|k| k.to_owned());
+            let val = state.to_str_in_place(-1).map(|v| v.to_owned());
+            if let (Some(key), Some(val)) = (key, val) {
+                tags.insert(key, val);
+            }
         }
+        state.pop(1);
+    }
+    tags
+}
+
+impl<'a> Payload<'a> {
+    fn from_metric(m: sync::Arc<Option<metric::Telemetry>>,
+                   tags: &'a metric::TagMap,
+                   path: &'a str,
+                   store: &'a mut StateStore)
+                   -> Payload<'a> {
+        let mut pyld = Payload::blank(tags, path, store);
+        pyld.metrics.push(m);
+        pyld
+    }
 
+    fn from_log(l: sync::Arc<Option<metric::LogLine>>,
+                tags: &'a metric::TagMap,
+                path: &'a str,
+                store: &'a mut StateStore)
+                -> Payload<'a> {
+        let mut pyld = Payload::blank(tags, path, store);
+        pyld.logs.push(l);
+        pyld
     }
 
-    fn blank(tags: &'a metric::TagMap, path: &'a str) -> Payload<'a> {
+    fn blank(tags: &'a metric::TagMap, path: &'a str, store: &'a mut StateStore) -> Payload<'a> {
         Payload {
             metrics: Vec::new(),
             logs: Vec::new(),
             global_tags: tags,
             path: path,
+            store: store,
+        }
+    }
+
+    fn from_batch(events: Vec<metric::Event>,
+                  tags: &'a metric::TagMap,
+                  path: &'a str,
+                  store: &'a mut StateStore)
+                  -> Payload<'a> {
+        let mut pyld = Payload::blank(tags, path, store);
+        for event in events {
+            match event {
+                metric::Event::Telemetry(m) => pyld.metrics.push(m),
+                metric::Event::Log(l) => pyld.logs.push(l),
+                metric::Event::TimerFlush => {}
+            }
+        }
+        pyld
+    }
+
+    /// Move the payload's logs and metrics, in that order, into `res`
+    fn emit(self, res: &mut Vec<metric::Event>) {
+        for lg in self.logs {
+            res.push(metric::Event::Log(lg));
+        }
+        for mt in self.metrics {
+            res.push(metric::Event::Telemetry(mt));
+        }
+    }
+
+    /// The metric at `idx`
+    fn metric(&self, idx: usize) -> &metric::Telemetry {
+        (*self.metrics[idx]).as_ref().unwrap()
+    }
+
+    /// The metric at `idx`, copied first if it is shared
+    fn metric_mut(&mut self, idx: usize) -> &mut metric::Telemetry {
+        sync::Arc::make_mut(&mut self.metrics[idx]).as_mut().unwrap()
+    }
+
+    /// The log at `idx`
+    fn log(&self, idx: usize) -> &metric::LogLine {
+        (*self.logs[idx]).as_ref().unwrap()
+    }
+
+    /// The log at `idx`, copied first if it is shared
+    fn log_mut(&mut self, idx: usize) -> &mut metric::LogLine {
+        sync::Arc::make_mut(&mut self.logs[idx]).as_mut().unwrap()
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_state_get(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let key = state.check_string(2).to_owned();
+        match (*pyld).store.get(&key) {
+            Some(&StateValue::Number(n)) => state.push_number(n),
+            Some(&StateValue::Text(ref s)) => state.push_string(s),
+            None => state.push_nil(),
         }
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_state_set(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let key = state.check_string(2).to_owned();
+        let val = match state.type_of(3) {
+            Some(Type::Number) => Some(StateValue::Number(state.to_number(3))),
+            Some(Type::String) => state.to_str_in_place(3).map(|v| StateValue::Text(v.to_owned())),
+            Some(Type::Nil) | None => None,
+            Some(other) => {
+                error!("[state_set] cannot store a {:?}", other);
+                return 0;
+            }
+        };
+        (*pyld).store.set(key, val);
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_state_incr(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let by = if state.is_number(3) {
+            state.to_number(3)
+        } else {
+            1.0
+        };
+        let key = state.check_string(2).to_owned();
+        let sum = (*pyld).store.incr(key, by);
+        state.push_number(sum);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_metric_count(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        state.push_integer((*pyld).metrics.len() as i64);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_count(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        state.push_integer((*pyld).logs.len() as i64);
+        1
     }
 
     #[allow(non_snake_case)]
     unsafe extern "C" fn lua_metric_name(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
-        state.push_string(&(*pyld).metrics[idx].name);
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        state.push_string(&(*pyld).metric(idx).name);
         1
     }
 
@@ -66,8 +275,8 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_set_metric_name(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
-        (*pyld).metrics[idx].name = state.check_string(3).into();
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        (*pyld).metric_mut(idx).name = state.check_string(3).into();
         0
     }
 
@@ -75,34 +284,200 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_push_metric(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
+        if state.is_table(2) {
+            return Payload::push_metric_table(&mut state, pyld);
+        }
         let val = state.to_number(3);
-        match state.to_str(2) {
-            Some(name) => {
-                let m = metric::Telemetry::new(name, val)
-                    .overlay_tags_from_map((*pyld).global_tags);
-                (*pyld).metrics.push(Box::new(m));
-            }
+        let m = metric::Telemetry::new(state.check_string(2), val)
+            .overlay_tags_from_map((*pyld).global_tags);
+        (*pyld).metrics.push(sync::Arc::new(Some(m)));
+        0
+    }
+
+    /// Push a metric described by the table at stack index 2. The table must
+    /// have a string `name` and numeric `value`. It may have an `aggr` of
+    /// "set", "sum" or "summarize", an integer `timestamp`, a boolean
+    /// `persist` and a `tags` table, overlaid on the global tags.
+    unsafe fn push_metric_table(state: &mut State, pyld: *mut Payload) -> c_int {
+        state.get_field(2, "name");
+        let name = string_at(state, -1);
+        state.pop(1);
+        let name = match name {
+            Some(name) => name,
             None => {
-                error!("[push_metric] no name argument given");
+                error!("[push_metric] no name field given");
+                return 0;
             }
+        };
+        state.get_field(2, "value");
+        if !state.is_number(-1) {
+            error!("[push_metric] no value field given");
+            state.pop(1);
+            return 0;
+        }
+        let mut m = metric::Telemetry::new(name, state.to_number(-1))
+            .overlay_tags_from_map((*pyld).global_tags);
+        state.pop(1);
+
+        state.get_field(2, "aggr");
+        if let Some(aggr) = string_at(state, -1) {
+            match aggr_method(&aggr) {
+                Some(method) => m.aggr_method = method,
+                None => error!("[push_metric] unknown aggregation {}", aggr),
+            }
+        }
+        state.pop(1);
+
+        state.get_field(2, "timestamp");
+        if state.is_number(-1) {
+            m = m.timestamp(state.to_integer(-1));
         }
+        state.pop(1);
+
+        state.get_field(2, "persist");
+        m.persist = state.to_bool(-1);
+        state.pop(1);
+
+        state.get_field(2, "tags");
+        if state.is_table(-1) {
+            let top = state.get_top();
+            let tags = table_to_tags(state, top);
+            m = m.overlay_tags_from_map(&tags);
+        }
+        state.pop(1);
+
+        (*pyld).metrics.push(sync::Arc::new(Some(m)));
         0
     }
 
     #[allow(non_snake_case)]
-    unsafe extern "C" fn lua_push_log(L: *mut lua_State) -> c_int {
+    unsafe extern "C" fn lua_drop_metric(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        match state.to_str(2) {
-            Some(line) => {
-                let l = metric::LogLine::new((*pyld).path, line)
-                    .overlay_tags_from_map((*pyld).global_tags);
-                (*pyld).logs.push(Box::new(l));
+        let n = state.to_integer(2);
+        match position(n, (*pyld).metrics.len()) {
+            Some(idx) => {
+                (*pyld).metrics.remove(idx);
             }
-            None => {
-                error!("[push_log] no line argument given");
+            None => error!("[drop_metric] no metric at index {}", n),
+        }
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_drop_log(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let n = state.to_integer(2);
+        match position(n, (*pyld).logs.len()) {
+            Some(idx) => {
+                (*pyld).logs.remove(idx);
             }
-        };
+            None => error!("[drop_log] no log at index {}", n),
+        }
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_clone_metric(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        let m = (*pyld).metrics[idx].clone();
+        (*pyld).metrics.push(m);
+        state.push_integer((*pyld).metrics.len() as i64);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_clone_log(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        let l = (*pyld).logs[idx].clone();
+        (*pyld).logs.push(l);
+        state.push_integer((*pyld).logs.len() as i64);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_metric_set_aggr(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        let aggr = state.check_string(3).to_owned();
+        match aggr_method(&aggr) {
+            Some(method) => (*pyld).metric_mut(idx).aggr_method = method,
+            None => error!("[metric_set_aggr] unknown aggregation {}", aggr),
+        }
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_metric_set_persist(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        (*pyld).metric_mut(idx).persist = state.to_bool(3);
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_metric_set_timestamp(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        if state.is_number(3) {
+            (*pyld).metric_mut(idx).timestamp = state.to_integer(3);
+        } else {
+            error!("[metric_set_timestamp] no timestamp provided");
+        }
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_value(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        state.push_string(&(*pyld).log(idx).value);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_set_value(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        (*pyld).log_mut(idx).value = state.check_string(3).into();
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_path(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        state.push_string(&(*pyld).log(idx).path);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_set_path(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        (*pyld).log_mut(idx).path = state.check_string(3).into();
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_push_log(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let l = metric::LogLine::new((*pyld).path, state.check_string(2))
+            .overlay_tags_from_map((*pyld).global_tags);
+        (*pyld).logs.push(sync::Arc::new(Some(l)));
         0
     }
 
@@ -110,8 +485,8 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_metric_value(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
-        match (*pyld).metrics[idx].value() {
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        match (*pyld).metric(idx).value() {
             Some(v) => {
                 state.push_number(v);
             }
@@ -126,10 +501,10 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_log_tag_value(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).logs.len());
+        let idx = check_idx(&mut state, (*pyld).logs.len());
         match state.to_str(3).map(|k| k.to_owned()) {
             Some(key) => {
-                match (*pyld).logs[idx].tags.get(&key) {
+                match (*pyld).log(idx).tags.get(&key) {
                     Some(v) => {
                         state.push_string(v);
                     }
@@ -150,10 +525,10 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_metric_tag_value(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
         match state.to_str(3).map(|k| k.to_owned()) {
             Some(key) => {
-                match (*pyld).metrics[idx].tags.get(&key) {
+                match (*pyld).metric(idx).tags.get(&key) {
                     Some(v) => {
                         state.push_string(v);
                     }
@@ -174,12 +549,13 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_metric_set_tag(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
         match state.to_str(3).map(|k| k.to_owned()) {
             Some(key) => {
                 match state.to_str(4).map(|v| v.to_owned()) {
                     Some(val) => {
-                        match sync::Arc::make_mut(&mut (*pyld).metrics[idx].tags).insert(key, val) {
+                        let tags = &mut (*pyld).metric_mut(idx).tags;
+                        match sync::Arc::make_mut(tags).insert(key, val) {
                             Some(old_v) => {
                                 state.push_string(&old_v);
                             }
@@ -206,12 +582,12 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_log_set_tag(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).logs.len());
+        let idx = check_idx(&mut state, (*pyld).logs.len());
         match state.to_str(3).map(|k| k.to_owned()) {
             Some(key) => {
                 match state.to_str(4).map(|v| v.to_owned()) {
                     Some(val) => {
-                        match (*pyld).logs[idx].tags.insert(key, val) {
+                        match (*pyld).log_mut(idx).tags.insert(key, val) {
                             Some(old_v) => {
                                 state.push_string(&old_v);
                             }
@@ -234,14 +610,58 @@ impl<'a> Payload<'a> {
         1
     }
 
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_metric_tags(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        push_tags(&mut state, &(*pyld).metric(idx).tags);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_tags(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        push_tags(&mut state, &(*pyld).log(idx).tags);
+        1
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_metric_set_tags(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        if state.is_table(3) {
+            (*pyld).metric_mut(idx).tags = sync::Arc::new(table_to_tags(&mut state, 3));
+        } else {
+            error!("[metric_set_tags] no tags table provided");
+        }
+        0
+    }
+
+    #[allow(non_snake_case)]
+    unsafe extern "C" fn lua_log_set_tags(L: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(L);
+        let pyld = state.to_userdata(1) as *mut Payload;
+        let idx = check_idx(&mut state, (*pyld).logs.len());
+        if state.is_table(3) {
+            (*pyld).log_mut(idx).tags = table_to_tags(&mut state, 3);
+        } else {
+            error!("[log_set_tags] no tags table provided");
+        }
+        0
+    }
+
     #[allow(non_snake_case)]
     unsafe extern "C" fn lua_metric_remove_tag(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
         match state.to_str(3).map(|k| k.to_owned()) {
             Some(key) => {
-                match sync::Arc::make_mut(&mut (*pyld).metrics[idx].tags).remove(&key) {
+                match sync::Arc::make_mut(&mut (*pyld).metric_mut(idx).tags).remove(&key) {
                     Some(old_v) => {
                         state.push_string(&old_v);
                     }
@@ -262,10 +682,10 @@ impl<'a> Payload<'a> {
     unsafe extern "C" fn lua_log_remove_tag(L: *mut lua_State) -> c_int {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
-        let idx = idx(state.to_integer(2), (*pyld).logs.len());
+        let idx = check_idx(&mut state, (*pyld).logs.len());
         match state.to_str(3).map(|k| k.to_owned()) {
             Some(key) => {
-                match (*pyld).logs[idx].tags.remove(&key) {
+                match (*pyld).log_mut(idx).tags.remove(&key) {
                     Some(old_v) => {
                         state.push_string(&old_v);
                     }
@@ -287,8 +707,8 @@ impl<'a> Payload<'a> {
         let mut state = State::from_ptr(L);
         let pyld = state.to_userdata(1) as *mut Payload;
         let prcnt = state.to_number(2);
-        let idx = idx(state.to_integer(2), (*pyld).metrics.len());
-        match (*pyld).metrics[idx].query(prcnt) {
+        let idx = check_idx(&mut state, (*pyld).metrics.len());
+        match (*pyld).metric(idx).query(prcnt) {
             Some(v) => {
                 state.push_number(v);
             }
@@ -300,174 +720,546 @@ impl<'a> Payload<'a> {
     }
 }
 
-const PAYLOAD_LIB: [(&'static str, Function); 12] =
-    [("metric_name", Some(Payload::lua_metric_name)),
+const PAYLOAD_LIB: [(&'static str, Function); 32] =
+    [("clone_log", Some(Payload::lua_clone_log)),
+     ("clone_metric", Some(Payload::lua_clone_metric)),
+     ("drop_log", Some(Payload::lua_drop_log)),
+     ("drop_metric", Some(Payload::lua_drop_metric)),
+     ("metric_count", Some(Payload::lua_metric_count)),
+     ("metric_name", Some(Payload::lua_metric_name)),
      ("metric_query", Some(Payload::lua_metric_query)),
+     ("log_count", Some(Payload::lua_log_count)),
+     ("log_path", Some(Payload::lua_log_path)),
      ("log_remove_tag", Some(Payload::lua_log_remove_tag)),
+     ("log_set_path", Some(Payload::lua_log_set_path)),
      ("log_set_tag", Some(Payload::lua_log_set_tag)),
+     ("log_set_tags", Some(Payload::lua_log_set_tags)),
+     ("log_set_value", Some(Payload::lua_log_set_value)),
      ("log_tag_value", Some(Payload::lua_log_tag_value)),
+     ("log_tags", Some(Payload::lua_log_tags)),
+     ("log_value", Some(Payload::lua_log_value)),
      ("metric_remove_tag", Some(Payload::lua_metric_remove_tag)),
+     ("metric_set_aggr", Some(Payload::lua_metric_set_aggr)),
+     ("metric_set_persist", Some(Payload::lua_metric_set_persist)),
      ("metric_set_tag", Some(Payload::lua_metric_set_tag)),
+     ("metric_set_tags", Some(Payload::lua_metric_set_tags)),
+     ("metric_set_timestamp", Some(Payload::lua_metric_set_timestamp)),
      ("metric_tag_value", Some(Payload::lua_metric_tag_value)),
+     ("metric_tags", Some(Payload::lua_metric_tags)),
      ("metric_value", Some(Payload::lua_metric_value)),
      ("push_log", Some(Payload::lua_push_log)),
      ("push_metric", Some(Payload::lua_push_metric)),
-     ("set_metric_name", Some(Payload::lua_set_metric_name))];
+     ("set_metric_name", Some(Payload::lua_set_metric_name)),
+     ("state_get", Some(Payload::lua_state_get)),
+     ("state_incr", Some(Payload::lua_state_incr)),
+     ("state_set", Some(Payload::lua_state_set))];
 
 pub struct ProgrammableFilter {
-    state: lua::State,
+    state: Interpreter,
     path: String,
     global_tags: metric::TagMap,
+    config: ProgrammableFilterConfig,
+    script_modified: Option<SystemTime>,
+    reload_generation: usize,
+    last_reload_check: Instant,
+    on_error: ErrorPolicy,
+    errors: u64,
+    batch_size: usize,
+    store: StateStore,
+}
+
+/// A value from a filter's `args`, exposed to its script in the global
+/// `config` table
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptArg {
+    Boolean(bool),
+    Float(f64),
+    Integer(i64),
+    String(String),
+}
+
+/// What to do with an event when the script fails while processing it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorPolicy {
+    /// Send the event on unchanged
+    PassThrough,
+    /// Drop the event
+    Drop,
 }
 
 #[derive(Debug, Clone)]
 pub struct ProgrammableFilterConfig {
     pub script: PathBuf,
+    /// Parameters for the script, set as the global `config` table before
+    /// the script is loaded. Lets one script serve many filters.
+    pub args: HashMap<String, ScriptArg>,
+    pub on_error: ErrorPolicy,
+    /// Expose only the libraries in `SANDBOX` to the script. Off by default,
+    /// as scripts written before sandboxing may use the others.
+    pub sandbox: bool,
+    /// The number of Lua instructions one call into the script may run, by
+    /// default unlimited
+    pub instruction_limit: Option<u32>,
+    /// The number of bytes the script's `lua::State` may allocate
+    pub memory_limit: Option<usize>,
+    /// When greater than one, up to this many events are gathered and handed
+    /// to the script's `process_batch` in one call
+    pub batch_size: usize,
+    /// The script's `state_*` key / value state is kept under here
+    pub data_directory: PathBuf,
     pub forwards: Vec<String>,
     pub config_path: String,
     pub tags: metric::TagMap,
 }
 
-impl ProgrammableFilter {
-    pub fn new(config: ProgrammableFilterConfig) -> ProgrammableFilter {
-        let mut state = lua::State::new();
-        state.open_libs();
+impl Default for ProgrammableFilterConfig {
+    fn default() -> ProgrammableFilterConfig {
+        ProgrammableFilterConfig {
+            script: PathBuf::new(),
+            args: HashMap::new(),
+            on_error: ErrorPolicy::PassThrough,
+            sandbox: false,
+            instruction_limit: None,
+            memory_limit: None,
+            batch_size: 1,
+            data_directory: PathBuf::from("/tmp/cernan-data"),
+            forwards: Vec::new(),
+            config_path: "filters.programmable".to_string(),
+            tags: metric::TagMap::default(),
+        }
+    }
+}
 
-        state.new_table();
-        state.set_fns(&PAYLOAD_LIB, 0);
-        state.set_global("payload");
+fn modified(script: &Path) -> Option<SystemTime> {
+    fs::metadata(script).and_then(|m| m.modified()).ok()
+}
 
-        let script_path = &config.script.to_str().unwrap();
-        match state.load_file(script_path) {
-            ThreadStatus::Ok => trace!("was able to load script at {}", script_path),
-            ThreadStatus::SyntaxError => {
-                error!("syntax error in script at {}", script_path);
-                panic!()
+/// Run in a sandboxed `lua::State` once the base, coroutine, math, os,
+/// string, table and utf8 libraries are open. Removes the ability to load
+/// code from files or as bytecode and all of `os` but its clock functions.
+const SANDBOX: &'static str = r#"
+dofile = nil
+loadfile = nil
+local raw_load = load
+load = function(chunk, name, mode, env)
+   return raw_load(chunk, name, "t", env)
+end
+local time, clock, date, difftime = os.time, os.clock, os.date, os.difftime
+os = {time = time, clock = clock, date = date, difftime = difftime}
+"#;
+
+/// Allocation bookkeeping for an `Interpreter`
+struct Allocator {
+    used: usize,
+    limit: usize,
+}
+
+/// A `lua_Alloc` refusing to grow past the `Allocator` limit
+unsafe extern "C" fn limited_alloc(ud: *mut c_void,
+                                   ptr: *mut c_void,
+                                   osize: size_t,
+                                   nsize: size_t)
+                                   -> *mut c_void {
+    let alloc = &mut *(ud as *mut Allocator);
+    // When ptr is NULL osize encodes the type of the new object, not a size.
+    let old = if ptr.is_null() { 0 } else { osize };
+    if nsize == 0 {
+        libc::free(ptr);
+        alloc.used -= old;
+        return ptr::null_mut();
+    }
+    // Lua assumes shrinking never fails, so only growth is refused.
+    if nsize > old && alloc.used + (nsize - old) > alloc.limit {
+        return ptr::null_mut();
+    }
+    let new = libc::realloc(ptr, nsize);
+    if !new.is_null() {
+        alloc.used = alloc.used - old + nsize;
+    }
+    new
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn instruction_limit_hook(L: *mut lua_State, _: *mut lua::ffi::lua_Debug) {
+    let mut state = State::from_ptr(L);
+    state.push_string("instruction limit exceeded");
+    state.error();
+}
+
+/// A `lua::State` running a filter script, along with its resource limits
+struct Interpreter {
+    state: lua::State,
+    instruction_limit: Option<u32>,
+    // Referenced by the state's allocator, so must outlive it. See Drop.
+    _allocator: Box<Allocator>,
+}
+
+impl Deref for Interpreter {
+    type Target = lua::State;
+
+    fn deref(&self) -> &lua::State {
+        &self.state
+    }
+}
+
+impl DerefMut for Interpreter {
+    fn deref_mut(&mut self) -> &mut lua::State {
+        &mut self.state
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        // The state does not own its pointer, having been made by from_ptr.
+        unsafe {
+            lua::ffi::lua_close(self.state.as_ptr());
+        }
+    }
+}
+
+impl Interpreter {
+    /// Compile the script at `config.script` into a fresh `lua::State` and
+    /// run its top level. On failure the Lua error message is returned, as is
+    /// an error if the script defines none of `ENTRY_POINTS`.
+    fn load(config: &ProgrammableFilterConfig) -> Result<Interpreter, String> {
+        let mut allocator = Box::new(Allocator {
+            used: 0,
+            limit: config.memory_limit.unwrap_or(usize::max_value()),
+        });
+        let state = unsafe {
+            let ud = &mut *allocator as *mut Allocator as *mut c_void;
+            let raw = lua::ffi::lua_newstate(Some(limited_alloc), ud);
+            if raw.is_null() {
+                return Err("could not allocate a lua state".to_string());
+            }
+            State::from_ptr(raw)
+        };
+        let mut interp = Interpreter {
+            state: state,
+            instruction_limit: config.instruction_limit,
+            _allocator: allocator,
+        };
+
+        if config.sandbox {
+            interp.open_base();
+            interp.open_coroutine();
+            interp.open_math();
+            interp.open_os();
+            interp.open_string();
+            interp.open_table();
+            interp.open_utf8();
+            match interp.do_string(SANDBOX) {
+                ThreadStatus::Ok => {}
+                status => return Err(format!("could not set up sandbox: {:?}", status)),
             }
-            other => {
-                error!("unknown status: {:?}", other);
-                panic!()
+        } else {
+            interp.open_libs();
+        }
+
+        interp.new_table();
+        interp.set_fns(&PAYLOAD_LIB, 0);
+        interp.set_global("payload");
+
+        interp.new_table();
+        for (key, arg) in &config.args {
+            match *arg {
+                ScriptArg::Boolean(b) => interp.push_bool(b),
+                ScriptArg::Float(f) => interp.push_number(f),
+                ScriptArg::Integer(i) => interp.push_integer(i),
+                ScriptArg::String(ref s) => interp.push_string(s),
             }
+            interp.set_field(-2, key);
         }
-        match state.pcall(0, 0, 0) {
+        interp.set_global("config");
+
+        let script = &config.script;
+        let script_path = match script.to_str() {
+            Some(p) => p,
+            None => return Err(format!("script path {:?} is not valid UTF-8", script)),
+        };
+        match interp.load_file(script_path) {
             ThreadStatus::Ok => trace!("was able to load script at {}", script_path),
-            ThreadStatus::SyntaxError => {
-                error!("syntax error in script at {}", script_path);
-                panic!()
+            status => {
+                let msg = interp.to_str(-1).map(|m| m.to_owned()).unwrap_or_default();
+                return Err(format!("could not load script at {} ({:?}): {}",
+                                   script_path,
+                                   status,
+                                   msg));
             }
-            other => {
-                error!("unknown status: {:?}", other);
-                panic!()
+        }
+        interp.limit_instructions();
+        match interp.pcall(0, 0, 0) {
+            ThreadStatus::Ok => trace!("was able to run script at {}", script_path),
+            status => {
+                let msg = interp.to_str(-1).map(|m| m.to_owned()).unwrap_or_default();
+                return Err(format!("could not run script at {} ({:?}): {}",
+                                   script_path,
+                                   status,
+                                   msg));
+            }
+        }
+        if !ENTRY_POINTS.iter().any(|func| interp.defines(func)) {
+            return Err(format!("script at {} defines none of {}",
+                               script_path,
+                               ENTRY_POINTS.join(", ")));
+        }
+        Ok(interp)
+    }
+
+    /// Whether the script defines a global function `func`
+    fn defines(&mut self, func: &str) -> bool {
+        self.get_global(func);
+        let defined = self.is_fn(-1);
+        self.pop(1);
+        defined
+    }
+
+    /// Start counting instructions towards the limit from zero
+    fn limit_instructions(&mut self) {
+        if let Some(limit) = self.instruction_limit {
+            let count = if limit > c_int::max_value() as u32 {
+                c_int::max_value()
+            } else {
+                limit as c_int
+            };
+            unsafe {
+                lua::ffi::lua_sethook(self.state.as_ptr(),
+                                      Some(instruction_limit_hook),
+                                      lua::ffi::LUA_MASKCOUNT,
+                                      count);
             }
         }
+    }
 
-        ProgrammableFilter {
-            state: state,
-            path: config.config_path,
-            global_tags: config.tags,
+    /// Call the global function `func`, which must exist, with `pyld` as its
+    /// only argument. On error the Lua error message is returned.
+    fn invoke(&mut self, func: &str, pyld: &mut Payload) -> Result<(), String> {
+        self.get_global(func);
+        unsafe {
+            self.push_light_userdata::<Payload>(pyld);
+        }
+        self.get_metatable_from_registry("payload");
+        self.set_metatable(-2);
+
+        self.limit_instructions();
+        match self.pcall(1, 0, 0) {
+            ThreadStatus::Ok => Ok(()),
+            status => {
+                let msg = self.to_str(-1)
+                    .map(|m| m.to_owned())
+                    .unwrap_or_else(|| format!("{:?}", status));
+                self.pop(1);
+                Err(msg)
+            }
         }
     }
 }
 
-impl filter::Filter for ProgrammableFilter {
-    fn process(&mut self,
-               event: metric::Event,
-               res: &mut Vec<metric::Event>)
-               -> Result<(), filter::FilterError> {
-        match event {
-            metric::Event::Telemetry(mut m) => {
-                self.state.get_global("process_metric");
-                if !self.state.is_fn(-1) {
-                    let fail =
-                        metric::Event::Telemetry(sync::Arc::new(Some(metric::Telemetry::new(format!("cernan.filture.\
-                                                                              {}.process_metric.\
-                                                                              failure",
-                                                                             self.path),
-                                                                     1.0)
-                            .aggr_sum())));
-                    return Err(filter::FilterError::NoSuchFunction("process_metric", fail));
-                }
+impl ProgrammableFilter {
+    /// Create a filter running the script at `config.script`
+    ///
+    /// Fails with the Lua error message if the script can't be loaded or
+    /// raises an error when run, or if it defines none of `process_metric`,
+    /// `process_log` and `process_batch`.
+    pub fn new(config: ProgrammableFilterConfig) -> Result<ProgrammableFilter, String> {
+        let state = Interpreter::load(&config)?;
+        let mut store = StateStore::new(state_path(&config.data_directory, &config.config_path));
+        if let Err(e) = store.read() {
+            error!("unable to read state for {}: {}", config.config_path, e);
+        }
 
-                let mut pyld = Payload::from_metric(sync::Arc::make_mut(&mut m).take().unwrap(),
-                                                    &self.global_tags,
-                                                    self.path.as_str());
-                unsafe {
-                    self.state.push_light_userdata::<Payload>(&mut pyld);
-                }
-                self.state.get_metatable_from_registry("payload");
-                self.state.set_metatable(-2);
+        Ok(ProgrammableFilter {
+            state: state,
+            path: config.config_path.clone(),
+            global_tags: config.tags.clone(),
+            script_modified: modified(&config.script),
+            reload_generation: RELOAD_GENERATION.load(Ordering::SeqCst),
+            last_reload_check: Instant::now(),
+            on_error: config.on_error.clone(),
+            errors: 0,
+            batch_size: config.batch_size,
+            store: store,
+            config: config,
+        })
+    }
 
-                self.state.call(1, 0);
+    fn no_such_function(&self, func: &'static str) -> filter::FilterError {
+        let fail = metric::Telemetry::new(format!("cernan.filter.{}.{}.failure", self.path, func),
+                                          1.0)
+            .aggr_sum();
+        filter::FilterError::NoSuchFunction(func, metric::Event::new_telemetry(fail))
+    }
 
-                for lg in pyld.logs {
-                    res.push(metric::Event::new_log(*lg));
-                }
-                for mt in pyld.metrics {
-                    res.push(metric::Event::new_telemetry(*mt));
-                }
-                Ok(())
+    /// Recompile the script in a fresh `lua::State`
+    ///
+    /// The new state replaces the running one only if the script loads as it
+    /// would in `new`. Otherwise the running state is kept, along with all
+    /// its globals, and the reason is returned.
+    pub fn reload(&mut self) -> Result<(), String> {
+        self.script_modified = modified(&self.config.script);
+        self.state = Interpreter::load(&self.config)?;
+        Ok(())
+    }
+
+    /// Reload the script if it has been modified on disk or a SIGHUP has been
+    /// received since the last check.
+    fn maybe_reload(&mut self) {
+        let generation = RELOAD_GENERATION.load(Ordering::SeqCst);
+        let signalled = generation != self.reload_generation;
+        let interval = Duration::from_secs(RELOAD_CHECK_INTERVAL);
+        if !signalled && self.last_reload_check.elapsed() < interval {
+            return;
+        }
+        self.reload_generation = generation;
+        self.last_reload_check = Instant::now();
+        if !signalled && modified(&self.config.script) == self.script_modified {
+            return;
+        }
+        match self.reload() {
+            Ok(()) => info!("reloaded script for {} from {:?}", self.path, self.config.script),
+            Err(e) => error!("keeping running script for {}: {}", self.path, e),
+        }
+    }
+
+    /// Run the script function appropriate to `event`
+    fn process_event(&mut self,
+                     event: metric::Event,
+                     res: &mut Vec<metric::Event>)
+                     -> Result<(), filter::FilterError> {
+        let func = match event {
+            metric::Event::Telemetry(_) => "process_metric",
+            metric::Event::Log(_) => "process_log",
+            metric::Event::TimerFlush => "tick",
+        };
+        if !self.state.defines(func) {
+            // A script need not define `tick`; the flush still reports errors.
+            if func == "tick" {
+                self.report_errors(res);
+                return Ok(());
+            }
+            return Err(self.no_such_function(func));
+        }
+
+        // The payload shares the event with the pass-through copy, so it's
+        // only copied if the script modifies it.
+        let pass_through = match event {
+            metric::Event::TimerFlush => Vec::new(),
+            _ if self.on_error == ErrorPolicy::PassThrough => vec![event.clone()],
+            _ => Vec::new(),
+        };
+        let mut pyld = match event {
+            metric::Event::Telemetry(m) => {
+                Payload::from_metric(m,
+                                     &self.global_tags,
+                                     self.path.as_str(),
+                                     &mut self.store)
+            }
+            metric::Event::Log(l) => {
+                Payload::from_log(l,
+                                  &self.global_tags,
+                                  self.path.as_str(),
+                                  &mut self.store)
             }
             metric::Event::TimerFlush => {
-                self.state.get_global("tick");
-                if !self.state.is_fn(-1) {
-                    let fail =
-                        metric::Event::new_telemetry(metric::Telemetry::new(format!("cernan.filter.\
-                                                                                  {}.tick.failure",
-                                                                                 self.path),
-                                                                         1.0)
-                            .aggr_sum());
-                    return Err(filter::FilterError::NoSuchFunction("tick", fail));
-                }
+                Payload::blank(&self.global_tags, self.path.as_str(), &mut self.store)
+            }
+        };
 
-                let mut pyld = Payload::blank(&self.global_tags, self.path.as_str());
-                unsafe {
-                    self.state.push_light_userdata::<Payload>(&mut pyld);
-                }
-                self.state.get_metatable_from_registry("payload");
-                self.state.set_metatable(-2);
+        if let Err(msg) = self.state.invoke(func, &mut pyld) {
+            self.errors += 1;
+            let mut pass_through = pass_through;
+            if func == "tick" {
+                // The failed tick is counted with the rest, the report going
+                // out alongside the failure.
+                self.report_errors(&mut pass_through);
+            }
+            return Err(filter::FilterError::ScriptError(format!("{} failed: {}", func, msg),
+                                                        pass_through));
+        }
 
-                self.state.call(1, 0);
+        pyld.emit(res);
+        if func == "tick" {
+            self.report_errors(res);
+        }
+        Ok(())
+    }
 
-                for lg in pyld.logs {
-                    res.push(metric::Event::new_log(*lg));
-                }
-                for mt in pyld.metrics {
-                    res.push(metric::Event::new_telemetry(*mt));
-                }
-                Ok(())
-            }
-            metric::Event::Log(mut l) => {
-                self.state.get_global("process_log");
-                if !self.state.is_fn(-1) {
-                    let fail =
-                        metric::Event::new_telemetry(metric::Telemetry::new(format!("cernan.filter.\
-                                                                                  {}.process_log.\
-                                                                                  failure",
-                                                                                 self.path),
-                                                                         1.0)
-                            .aggr_sum());
-                    return Err(filter::FilterError::NoSuchFunction("process_log", fail));
-                }
+    /// Report the script errors seen since the last flush, if any
+    fn report_errors(&mut self, res: &mut Vec<metric::Event>) {
+        if self.errors > 0 {
+            let errors = metric::Telemetry::new(format!("cernan.filter.{}.script_error",
+                                                        self.path),
+                                                self.errors as f64)
+                .aggr_sum()
+                .overlay_tags_from_map(&self.global_tags);
+            res.push(metric::Event::new_telemetry(errors));
+            self.errors = 0;
+        }
+    }
+}
 
-                let mut pyld = Payload::from_log(sync::Arc::make_mut(&mut l).take().unwrap(),
-                                                 &self.global_tags,
-                                                 self.path.as_str());
-                unsafe {
-                    self.state.push_light_userdata::<Payload>(&mut pyld);
-                }
-                self.state.get_metatable_from_registry("payload");
-                self.state.set_metatable(-2);
+impl filter::Filter for ProgrammableFilter {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        self.maybe_reload();
+        let flush = match event {
+            metric::Event::TimerFlush => true,
+            _ => false,
+        };
+        let result = self.process_event(event, res);
+        if flush {
+            if let Err(e) = self.store.write() {
+                error!("unable to write state for {}: {}", self.path, e);
+            }
+        }
+        result
+    }
 
-                self.state.call(1, 0);
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
 
-                for lg in pyld.logs {
-                    res.push(metric::Event::new_log(*lg));
-                }
-                for mt in pyld.metrics {
-                    res.push(metric::Event::new_telemetry(*mt));
+    /// Hand all of `events` to the script's `process_batch` at once, or to
+    /// `process_metric` and `process_log` in turn if it defines none
+    fn process_batch(&mut self,
+                     events: Vec<metric::Event>,
+                     res: &mut Vec<metric::Event>)
+                     -> Result<(), filter::FilterError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.maybe_reload();
+        if !self.state.defines("process_batch") {
+            // Run the events one at a time, as if unbatched, so that a failure
+            // costs only the event that caused it.
+            for event in events {
+                if let Err(fe) = self.process_event(event, res) {
+                    error!("Failed to run filter with error: {:?}", filter::name_in_fe(&fe));
+                    res.extend(filter::events_in_fe(fe));
                 }
-                Ok(())
             }
+            return Ok(());
         }
+
+        let pass_through = if self.on_error == ErrorPolicy::PassThrough {
+            events.clone()
+        } else {
+            Vec::new()
+        };
+        let mut pyld = Payload::from_batch(events,
+                                           &self.global_tags,
+                                           self.path.as_str(),
+                                           &mut self.store);
+
+        if let Err(msg) = self.state.invoke("process_batch", &mut pyld) {
+            self.errors += 1;
+            return Err(filter::FilterError::ScriptError(format!("process_batch failed: {}", msg),
+                                                        pass_through));
+        }
+
+        pyld.emit(res);
+        Ok(())
     }
 }
@@ -0,0 +1,266 @@
+use filter;
+use glob::Pattern;
+use metric;
+use regex::Regex;
+use std::sync;
+
+/// Rewrite tags and metric names, or drop events, by rule.
+///
+/// Rules are applied in order and every matching rule applies, each seeing
+/// the event as rewritten by the rules before it. A rule matches when all of
+/// its conditions hold: the telemetry name matches `name`, every tag in
+/// `tags` is present with the given value or, if the value is `None`, merely
+/// present. A rule with a `name` condition only ever matches telemetry.
+/// Rules without one match log lines as well.
+///
+/// Once a rule drops an event no further rules are considered.
+pub struct Rewrite {
+    rules: Vec<RewriteRule>,
+}
+
+#[derive(Debug, Clone)]
+pub enum NameMatch {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RewriteAction {
+    /// Rename a metric. When the rule matched by regex, `$1` and `$name`
+    /// style references to its capture groups are expanded.
+    RenameMetric(String),
+    RenameTag(String, String),
+    SetTag(String, String),
+    RemoveTag(String),
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub name: Option<NameMatch>,
+    pub tags: Vec<(String, Option<String>)>,
+    pub actions: Vec<RewriteAction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RewriteConfig {
+    pub rules: Vec<RewriteRule>,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+}
+
+impl RewriteRule {
+    fn matches_tags(&self, tags: &metric::TagMap) -> bool {
+        self.tags.iter().all(|&(ref key, ref val)| match (tags.get(key), val) {
+            (Some(v), &Some(ref val)) => v == val,
+            (Some(_), &None) => true,
+            (None, _) => false,
+        })
+    }
+
+    /// Apply the actions which concern tags, returning false if the event
+    /// is to be dropped
+    fn rewrite_tags(&self, tags: &mut metric::TagMap) -> bool {
+        for action in &self.actions {
+            match *action {
+                RewriteAction::RenameMetric(_) => {}
+                RewriteAction::RenameTag(ref from, ref to) => {
+                    if let Some(val) = tags.remove(from) {
+                        tags.insert(to.clone(), val);
+                    }
+                }
+                RewriteAction::SetTag(ref key, ref val) => {
+                    tags.insert(key.clone(), val.clone());
+                }
+                RewriteAction::RemoveTag(ref key) => {
+                    tags.remove(key);
+                }
+                RewriteAction::Drop => return false,
+            }
+        }
+        true
+    }
+
+    fn rewrite_telemetry(&self, telem: &mut metric::Telemetry) -> bool {
+        let mut new_name = None;
+        match self.name {
+            Some(NameMatch::Glob(ref pattern)) => {
+                if !pattern.matches(&telem.name) {
+                    return true;
+                }
+                for action in &self.actions {
+                    if let RewriteAction::RenameMetric(ref name) = *action {
+                        new_name = Some(name.clone());
+                    }
+                }
+            }
+            Some(NameMatch::Regex(ref re)) => {
+                match re.captures(&telem.name) {
+                    Some(caps) => {
+                        for action in &self.actions {
+                            if let RewriteAction::RenameMetric(ref template) = *action {
+                                let mut name = String::new();
+                                caps.expand(template, &mut name);
+                                new_name = Some(name);
+                            }
+                        }
+                    }
+                    None => return true,
+                }
+            }
+            None => {}
+        }
+        if !self.matches_tags(&telem.tags) {
+            return true;
+        }
+        if let Some(name) = new_name {
+            telem.name = name;
+        }
+        self.rewrite_tags(sync::Arc::make_mut(&mut telem.tags))
+    }
+
+    fn rewrite_log(&self, line: &mut metric::LogLine) -> bool {
+        if self.name.is_some() || !self.matches_tags(&line.tags) {
+            return true;
+        }
+        self.rewrite_tags(&mut line.tags)
+    }
+}
+
+impl Rewrite {
+    pub fn new(config: RewriteConfig) -> Rewrite {
+        Rewrite { rules: config.rules }
+    }
+}
+
+impl filter::Filter for Rewrite {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        match event {
+            metric::Event::Telemetry(mut m) => {
+                let mut telem = sync::Arc::make_mut(&mut m).take().unwrap();
+                for rule in &self.rules {
+                    if !rule.rewrite_telemetry(&mut telem) {
+                        return Ok(());
+                    }
+                }
+                res.push(metric::Event::new_telemetry(telem));
+            }
+            metric::Event::Log(mut l) => {
+                let mut line = sync::Arc::make_mut(&mut l).take().unwrap();
+                for rule in &self.rules {
+                    if !rule.rewrite_log(&mut line) {
+                        return Ok(());
+                    }
+                }
+                res.push(metric::Event::new_log(line));
+            }
+            metric::Event::TimerFlush => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use glob::Pattern;
+    use metric;
+    use regex::Regex;
+    use super::*;
+
+    fn rewrite(rules: Vec<RewriteRule>) -> Rewrite {
+        Rewrite::new(RewriteConfig {
+            rules: rules,
+            forwards: Vec::new(),
+            config_path: "filters.rewrite".to_string(),
+        })
+    }
+
+    fn telemetry(filter: &mut Rewrite, telem: metric::Telemetry) -> Option<metric::Telemetry> {
+        let mut events = Vec::new();
+        filter.process(metric::Event::new_telemetry(telem), &mut events).unwrap();
+        match events.pop() {
+            Some(metric::Event::Telemetry(t)) => t.as_ref().clone(),
+            None => None,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rename_metric_with_captures() {
+        let rule = RewriteRule {
+            name: Some(NameMatch::Regex(Regex::new(r"^collectd\.([^.]+)\.(.*)$").unwrap())),
+            tags: Vec::new(),
+            actions: vec![RewriteAction::RenameMetric("collectd.$2".to_string()),
+                          RewriteAction::SetTag("collectd_host".to_string(),
+                                                "yes".to_string())],
+        };
+        let mut filter = rewrite(vec![rule]);
+
+        let telem = telemetry(&mut filter,
+                              metric::Telemetry::new("collectd.web-1.cpu.idle", 1.0))
+            .unwrap();
+        assert_eq!(telem.name, "collectd.cpu.idle");
+        assert_eq!(telem.tags.get(&"collectd_host".to_string()),
+                   Some(&"yes".to_string()));
+
+        let telem = telemetry(&mut filter, metric::Telemetry::new("app.requests", 1.0)).unwrap();
+        assert_eq!(telem.name, "app.requests");
+        assert_eq!(telem.tags.get(&"collectd_host".to_string()), None);
+    }
+
+    #[test]
+    fn tag_predicates_and_actions() {
+        let rule = RewriteRule {
+            name: Some(NameMatch::Glob(Pattern::new("app.*").unwrap())),
+            tags: vec![("env".to_string(), Some("prod".to_string())),
+                       ("hostname".to_string(), None)],
+            actions: vec![RewriteAction::RenameTag("hostname".to_string(), "host".to_string()),
+                          RewriteAction::RemoveTag("env".to_string())],
+        };
+        let mut filter = rewrite(vec![rule]);
+
+        let telem = metric::Telemetry::new("app.requests", 1.0)
+            .overlay_tag("env", "prod")
+            .overlay_tag("hostname", "web-1");
+        let telem = telemetry(&mut filter, telem).unwrap();
+        assert_eq!(telem.tags.get(&"host".to_string()), Some(&"web-1".to_string()));
+        assert_eq!(telem.tags.get(&"hostname".to_string()), None);
+        assert_eq!(telem.tags.get(&"env".to_string()), None);
+
+        let telem = metric::Telemetry::new("app.requests", 1.0)
+            .overlay_tag("env", "staging")
+            .overlay_tag("hostname", "web-1");
+        let telem = telemetry(&mut filter, telem).unwrap();
+        assert_eq!(telem.tags.get(&"hostname".to_string()), Some(&"web-1".to_string()));
+        assert_eq!(telem.tags.get(&"env".to_string()), Some(&"staging".to_string()));
+    }
+
+    #[test]
+    fn drop_events() {
+        let drop_debug = RewriteRule {
+            name: None,
+            tags: vec![("level".to_string(), Some("debug".to_string()))],
+            actions: vec![RewriteAction::Drop],
+        };
+        let drop_internal = RewriteRule {
+            name: Some(NameMatch::Glob(Pattern::new("internal.*").unwrap())),
+            tags: Vec::new(),
+            actions: vec![RewriteAction::Drop],
+        };
+        let mut filter = rewrite(vec![drop_debug, drop_internal]);
+
+        assert!(telemetry(&mut filter, metric::Telemetry::new("internal.gc", 1.0)).is_none());
+        assert!(telemetry(&mut filter, metric::Telemetry::new("app.gc", 1.0)).is_some());
+
+        let mut events = Vec::new();
+        let debug = metric::LogLine::new("app.log", "noise").overlay_tag("level", "debug");
+        let info = metric::LogLine::new("app.log", "signal").overlay_tag("level", "info");
+        filter.process(metric::Event::new_log(debug), &mut events).unwrap();
+        filter.process(metric::Event::new_log(info), &mut events).unwrap();
+        assert_eq!(events.len(), 1);
+    }
+}
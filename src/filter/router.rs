@@ -0,0 +1,172 @@
+use hopper;
+use metric;
+use time;
+use util;
+
+/// Send events to different forwards depending on their content.
+///
+/// Each event is checked against the configured routes in order and sent
+/// only to the forwards of the first route that matches. A route matches
+/// when all of its conditions hold: the event is of the given `kind`, its
+/// name starts with `name_prefix` and it carries every tag in `tags` with the
+/// given value. For log lines the name is the line's path. Events which
+/// match no route are sent to the `default` forwards, or dropped if there
+/// are none.
+///
+/// Unlike other filters the router does not send every event to all of its
+/// forwards and so does not implement `Filter`.
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    Telemetry,
+    Log,
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub kind: Option<EventKind>,
+    pub name_prefix: Option<String>,
+    pub tags: Vec<(String, String)>,
+    pub forwards: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    pub routes: Vec<Route>,
+    pub default: Vec<String>,
+    pub config_path: String,
+}
+
+impl Route {
+    fn matches(&self, kind: EventKind, name: &str, tags: &metric::TagMap) -> bool {
+        if let Some(ref k) = self.kind {
+            if *k != kind {
+                return false;
+            }
+        }
+        if let Some(ref prefix) = self.name_prefix {
+            if !name.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        self.tags.iter().all(|&(ref key, ref val)| tags.get(key) == Some(val))
+    }
+}
+
+impl Router {
+    pub fn new(config: RouterConfig) -> Router {
+        Router { routes: config.routes }
+    }
+
+    /// Determine the index of the route `event` is to take, or None if it
+    /// takes the default
+    pub fn route(&self, event: &metric::Event) -> Option<usize> {
+        let (kind, name, tags) = match *event {
+            metric::Event::Telemetry(ref t) => {
+                match **t {
+                    Some(ref t) => (EventKind::Telemetry, t.name.as_str(), &*t.tags),
+                    None => return None,
+                }
+            }
+            metric::Event::Log(ref l) => {
+                match **l {
+                    Some(ref l) => (EventKind::Log, l.path.as_str(), &l.tags),
+                    None => return None,
+                }
+            }
+            metric::Event::TimerFlush => return None,
+        };
+        self.routes.iter().position(|r| r.matches(kind.clone(), name, tags))
+    }
+
+    /// Route events from `recv`
+    ///
+    /// `routes` holds the channels of each configured route, in order.
+    pub fn run(&mut self,
+               mut recv: hopper::Receiver<metric::Event>,
+               mut routes: Vec<util::Channel>,
+               mut default: util::Channel) {
+        let mut attempts = 0;
+        loop {
+            time::delay(attempts);
+            match recv.next() {
+                None => attempts += 1,
+                Some(metric::Event::TimerFlush) => attempts = 0,
+                Some(event) => {
+                    attempts = 0;
+                    let chans = match self.route(&event) {
+                        Some(idx) => &mut routes[idx],
+                        None => &mut default,
+                    };
+                    if !chans.is_empty() {
+                        util::send("router", chans, event);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use metric;
+    use super::*;
+
+    fn route(kind: Option<EventKind>,
+             name_prefix: Option<&str>,
+             tags: Vec<(&str, &str)>)
+             -> Route {
+        Route {
+            kind: kind,
+            name_prefix: name_prefix.map(|p| p.to_string()),
+            tags: tags.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+            forwards: Vec::new(),
+        }
+    }
+
+    fn router(routes: Vec<Route>) -> Router {
+        Router::new(RouterConfig {
+            routes: routes,
+            default: Vec::new(),
+            config_path: "filters.router".to_string(),
+        })
+    }
+
+    #[test]
+    fn first_matching_route_wins() {
+        let router = router(vec![route(None, Some("app."), vec![]),
+                                 route(None, Some("app.db."), vec![]),
+                                 route(None, Some("infra."), vec![])]);
+        let event = |name: &str| metric::Event::new_telemetry(metric::Telemetry::new(name, 1.0));
+
+        assert_eq!(router.route(&event("app.requests")), Some(0));
+        assert_eq!(router.route(&event("app.db.queries")), Some(0));
+        assert_eq!(router.route(&event("infra.cpu")), Some(2));
+        assert_eq!(router.route(&event("other.thing")), None);
+        assert_eq!(router.route(&metric::Event::TimerFlush), None);
+    }
+
+    #[test]
+    fn kind_and_tags() {
+        let router = router(vec![route(Some(EventKind::Log), None, vec![("level", "error")]),
+                                 route(Some(EventKind::Telemetry),
+                                       None,
+                                       vec![("env", "prod")]),
+                                 route(Some(EventKind::Log), Some("/var/log/"), vec![])]);
+
+        let error = metric::LogLine::new("/var/log/app.log", "boom").overlay_tag("level", "error");
+        let info = metric::LogLine::new("/var/log/app.log", "fine").overlay_tag("level", "info");
+        let other = metric::LogLine::new("/srv/app.log", "fine");
+        assert_eq!(router.route(&metric::Event::new_log(error)), Some(0));
+        assert_eq!(router.route(&metric::Event::new_log(info)), Some(2));
+        assert_eq!(router.route(&metric::Event::new_log(other)), None);
+
+        let prod = metric::Telemetry::new("app.requests", 1.0).overlay_tag("env", "prod");
+        let dev = metric::Telemetry::new("app.requests", 1.0).overlay_tag("env", "dev");
+        assert_eq!(router.route(&metric::Event::new_telemetry(prod)), Some(1));
+        assert_eq!(router.route(&metric::Event::new_telemetry(dev)), None);
+    }
+}
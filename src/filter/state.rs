@@ -0,0 +1,189 @@
+use serde_json;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// A value kept in a `StateStore`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateValue {
+    Number(f64),
+    Text(String),
+}
+
+/// Durable key / value state for a programmable filter.
+///
+/// State is kept in memory and flushed to disk on `write`. The on-disk format
+/// is a single JSON object. As with file source checkpoints, writes go to a
+/// temporary file which is then renamed over the old state so a crash
+/// mid-write never leaves a torn file behind.
+pub struct StateStore {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    values: HashMap<String, StateValue>,
+    dirty: bool,
+}
+
+/// Compute the location of a filter's state
+///
+/// Each filter gets its own state file under `<data_directory>/filter_state`,
+/// named for its config path.
+pub fn state_path(data_directory: &Path, config_path: &str) -> PathBuf {
+    let name: String = config_path.chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' {
+            c
+        } else {
+            '_'
+        })
+        .collect();
+    data_directory.join("filter_state").join(name)
+}
+
+impl StateStore {
+    pub fn new(path: PathBuf) -> StateStore {
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("tmp");
+        StateStore {
+            path: path,
+            tmp_path: tmp_path,
+            values: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Load state from disk, if any has been written
+    ///
+    /// A missing state file is not an error: it simply means this filter has
+    /// never kept state before. Values that are neither numbers nor strings
+    /// are skipped.
+    pub fn read(&mut self) -> io::Result<()> {
+        let mut fp = match fs::File::open(&self.path) {
+            Ok(fp) => fp,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut buf = String::new();
+        fp.read_to_string(&mut buf)?;
+        let obj = match serde_json::from_str::<Value>(&buf) {
+            Ok(Value::Object(obj)) => obj,
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("malformed state in {:?}", self.path)))
+            }
+        };
+        for (key, val) in obj {
+            let val = match val {
+                Value::I64(i) => StateValue::Number(i as f64),
+                Value::U64(u) => StateValue::Number(u as f64),
+                Value::F64(f) => StateValue::Number(f),
+                Value::String(s) => StateValue::Text(s),
+                _ => {
+                    warn!("malformed state in {:?} for key {}", self.path, key);
+                    continue;
+                }
+            };
+            self.values.insert(key, val);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&StateValue> {
+        self.values.get(key)
+    }
+
+    /// Set `key` to `val`, or remove it if `val` is None
+    pub fn set(&mut self, key: String, val: Option<StateValue>) {
+        match val {
+            Some(val) => {
+                if self.values.get(&key) != Some(&val) {
+                    self.values.insert(key, val);
+                    self.dirty = true;
+                }
+            }
+            None => {
+                if self.values.remove(&key).is_some() {
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Add `by` to the number at `key`, returning the sum. Missing keys and
+    /// keys holding text count as zero.
+    pub fn incr(&mut self, key: String, by: f64) -> f64 {
+        let sum = match self.values.get(&key) {
+            Some(&StateValue::Number(n)) => n + by,
+            _ => by,
+        };
+        self.set(key, Some(StateValue::Number(sum)));
+        sum
+    }
+
+    /// Persist state to disk, if it has changed since the last write
+    pub fn write(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut obj = Map::new();
+        for (key, val) in &self.values {
+            let val = match *val {
+                StateValue::Number(n) => Value::F64(n),
+                StateValue::Text(ref s) => Value::String(s.clone()),
+            };
+            obj.insert(key.clone(), val);
+        }
+        {
+            let mut fp = io::BufWriter::new(fs::File::create(&self.tmp_path)?);
+            let buf = serde_json::to_string(&Value::Object(obj))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fp.write_all(buf.as_bytes())?;
+            fp.flush()?;
+            fp.get_ref().sync_all()?;
+        }
+        fs::rename(&self.tmp_path, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn state_survives_round_trip() {
+        let dir = TempDir::new("cernan_filter_state").unwrap();
+        let path = state_path(dir.path(), "filters.running_total");
+
+        let mut store = StateStore::new(path.clone());
+        store.set("last_seen".to_string(), Some(StateValue::Text("web-1".to_string())));
+        assert_eq!(store.incr("total".to_string(), 2.5), 2.5);
+        assert_eq!(store.incr("total".to_string(), 1.0), 3.5);
+        store.set("gone".to_string(), Some(StateValue::Number(1.0)));
+        store.set("gone".to_string(), None);
+        store.write().unwrap();
+
+        let mut restored = StateStore::new(path);
+        restored.read().unwrap();
+        assert_eq!(restored.get("total"), Some(&StateValue::Number(3.5)));
+        assert_eq!(restored.get("last_seen"),
+                   Some(&StateValue::Text("web-1".to_string())));
+        assert_eq!(restored.get("gone"), None);
+    }
+
+    #[test]
+    fn missing_state_file_is_empty() {
+        let dir = TempDir::new("cernan_filter_state").unwrap();
+        let mut store = StateStore::new(dir.path().join("never_written"));
+        assert!(store.read().is_ok());
+        assert_eq!(store.get("total"), None);
+    }
+}
@@ -0,0 +1,46 @@
+//! Event factories and drains shared by the filters' unit tests.
+
+use filter::Filter;
+use metric;
+
+/// A log event from `path`
+pub fn log(path: &str, value: &str) -> metric::Event {
+    metric::Event::new_log(metric::LogLine::new(path, value))
+}
+
+/// Send `filter` a `TimerFlush`, returning what it emits
+pub fn flush<F: Filter>(filter: &mut F) -> Vec<metric::Event> {
+    let mut events = Vec::new();
+    filter.process(metric::Event::TimerFlush, &mut events).unwrap();
+    events
+}
+
+/// Copies of the telemetry among `events`, in order
+pub fn telemetry(events: &[metric::Event]) -> Vec<metric::Telemetry> {
+    events.iter()
+        .filter_map(|e| match *e {
+            metric::Event::Telemetry(ref t) => t.as_ref().clone(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Copies of the log lines among `events`, in order
+pub fn logs(events: &[metric::Event]) -> Vec<metric::LogLine> {
+    events.iter()
+        .filter_map(|e| match *e {
+            metric::Event::Log(ref l) => l.as_ref().clone(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The value of the tag `key` on `event`, be it telemetry or a log line
+pub fn tag(event: &metric::Event, key: &str) -> Option<String> {
+    let key = key.to_string();
+    match *event {
+        metric::Event::Telemetry(ref t) => t.as_ref().as_ref().unwrap().tags.get(&key).cloned(),
+        metric::Event::Log(ref l) => l.as_ref().as_ref().unwrap().tags.get(&key).cloned(),
+        metric::Event::TimerFlush => None,
+    }
+}
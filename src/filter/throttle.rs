@@ -0,0 +1,244 @@
+use filter;
+use metric;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Limit the rate of events per key.
+///
+/// Every key has a token bucket holding at most `burst` tokens which refills
+/// at `rate` tokens per second. An event that finds a token in its key's
+/// bucket takes it and passes. An event that does not is throttled, which
+/// depending on `mode` means:
+///
+///  * `Drop`: the event is dropped.
+///  * `Sample(n)`: one in every `n` throttled events passes, the rest are
+///    dropped.
+///  * `Summarize`: the event is dropped and counted. At a flush each key's
+///    count, if non-zero, is emitted as `cernan.throttle.dropped` tagged with
+///    `throttle_key`, and starts again from zero.
+///
+/// Buckets that have refilled completely are forgotten on flush, so only
+/// recently active keys take up memory.
+pub struct Throttle {
+    key: ThrottleKey,
+    rate: f64,
+    burst: f64,
+    mode: ThrottleMode,
+    tags: metric::TagMap,
+    buckets: HashMap<String, Bucket>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThrottleKey {
+    /// The telemetry name or the log line path
+    Name,
+    /// The value of the given tag. Events without the tag share a bucket.
+    Tag(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThrottleMode {
+    Drop,
+    Sample(u64),
+    Summarize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+    pub key: ThrottleKey,
+    pub rate: f64,
+    pub burst: f64,
+    pub mode: ThrottleMode,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub tags: metric::TagMap,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    throttled: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed = elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1_000_000_000.0);
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = now;
+    }
+}
+
+impl Throttle {
+    pub fn new(config: ThrottleConfig) -> Throttle {
+        Throttle {
+            key: config.key,
+            rate: config.rate,
+            burst: config.burst,
+            mode: config.mode,
+            tags: config.tags,
+            buckets: HashMap::new(),
+        }
+    }
+
+    fn key_of(&self, event: &metric::Event) -> Option<String> {
+        let (name, tags) = match *event {
+            metric::Event::Telemetry(ref t) => {
+                match **t {
+                    Some(ref t) => (&t.name, &*t.tags),
+                    None => return None,
+                }
+            }
+            metric::Event::Log(ref l) => {
+                match **l {
+                    Some(ref l) => (&l.path, &l.tags),
+                    None => return None,
+                }
+            }
+            metric::Event::TimerFlush => return None,
+        };
+        match self.key {
+            ThrottleKey::Name => Some(name.clone()),
+            ThrottleKey::Tag(ref key) => Some(tags.get(key).cloned().unwrap_or_default()),
+        }
+    }
+
+    /// Determine whether an event with the given key may pass
+    fn admit(&mut self, key: String, now: Instant) -> bool {
+        let burst = self.burst;
+        let rate = self.rate;
+        let bucket = self.buckets.entry(key).or_insert_with(|| {
+            Bucket {
+                tokens: burst,
+                last_refill: now,
+                throttled: 0,
+            }
+        });
+        bucket.refill(rate, burst, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        bucket.throttled += 1;
+        match self.mode {
+            ThrottleMode::Drop | ThrottleMode::Summarize => false,
+            ThrottleMode::Sample(n) => (bucket.throttled - 1) % n == 0,
+        }
+    }
+
+    fn flush(&mut self, res: &mut Vec<metric::Event>, now: Instant) {
+        let mut idle = Vec::new();
+        for (key, bucket) in &mut self.buckets {
+            if self.mode == ThrottleMode::Summarize && bucket.throttled > 0 {
+                let telem = metric::Telemetry::new("cernan.throttle.dropped",
+                                                   bucket.throttled as f64)
+                    .aggr_sum()
+                    .overlay_tags_from_map(&self.tags)
+                    .overlay_tag("throttle_key", key.as_str());
+                res.push(metric::Event::new_telemetry(telem));
+            }
+            bucket.throttled = 0;
+            bucket.refill(self.rate, self.burst, now);
+            if bucket.tokens >= self.burst {
+                idle.push(key.clone());
+            }
+        }
+        for key in idle {
+            self.buckets.remove(&key);
+        }
+    }
+}
+
+impl filter::Filter for Throttle {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        let now = Instant::now();
+        match self.key_of(&event) {
+            Some(key) => {
+                if self.admit(key, now) {
+                    res.push(event);
+                }
+            }
+            None => {
+                if let metric::Event::TimerFlush = event {
+                    self.flush(res, now);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::Filter;
+    use filter::test_support::{flush, tag, telemetry};
+    use metric;
+    use super::*;
+
+    fn throttle(key: ThrottleKey, mode: ThrottleMode) -> Throttle {
+        // With no refill only the initial burst is ever admitted.
+        Throttle::new(ThrottleConfig {
+            key: key,
+            rate: 0.0,
+            burst: 2.0,
+            mode: mode,
+            forwards: Vec::new(),
+            config_path: "filters.throttle".to_string(),
+            tags: metric::TagMap::default(),
+        })
+    }
+
+    fn log(path: &str, service: &str) -> metric::Event {
+        metric::Event::new_log(metric::LogLine::new(path, "line").overlay_tag("service", service))
+    }
+
+    #[test]
+    fn drop_per_key() {
+        let mut filter = throttle(ThrottleKey::Tag("service".to_string()), ThrottleMode::Drop);
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            filter.process(log("a.log", "noisy"), &mut events).unwrap();
+        }
+        assert_eq!(events.len(), 2);
+        filter.process(log("a.log", "quiet"), &mut events).unwrap();
+        assert_eq!(events.len(), 3);
+
+        // nothing to report in drop mode
+        assert!(flush(&mut filter).is_empty());
+    }
+
+    #[test]
+    fn sample() {
+        let mut filter = throttle(ThrottleKey::Name, ThrottleMode::Sample(3));
+        let mut events = Vec::new();
+        for _ in 0..11 {
+            filter.process(log("a.log", "noisy"), &mut events).unwrap();
+        }
+        // two from the burst, then the 1st, 4th, 7th of the nine throttled
+        assert_eq!(events.len(), 5);
+    }
+
+    #[test]
+    fn summarize() {
+        let mut filter = throttle(ThrottleKey::Name, ThrottleMode::Summarize);
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            filter.process(log("a.log", "noisy"), &mut events).unwrap();
+        }
+        filter.process(log("b.log", "noisy"), &mut events).unwrap();
+        assert_eq!(events.len(), 3);
+
+        let events = flush(&mut filter);
+        let telems = telemetry(&events);
+        assert_eq!(telems.len(), 1);
+        assert_eq!(telems[0].name, "cernan.throttle.dropped");
+        assert_eq!(telems[0].value(), Some(3.0));
+        assert_eq!(tag(&events[0], "throttle_key"), Some("a.log".to_string()));
+
+        // counts reset on flush
+        assert!(flush(&mut filter).is_empty());
+    }
+}
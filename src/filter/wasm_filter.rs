@@ -0,0 +1,465 @@
+//! Filters compiled to WebAssembly.
+//!
+//! A wasm filter is a module which exports its `memory` and the functions
+//!
+//! - `cernan_alloc(len: i32) -> i32`
+//! - `process_metric(ptr: i32, len: i32)`
+//! - `process_log(ptr: i32, len: i32)`
+//! - `tick(ptr: i32, len: i32)`
+//!
+//! Events cross the module boundary serialized, one JSON object per event
+//! as written by `filter::render_event`. Before each call the host asks
+//! `cernan_alloc` for `len` bytes and copies the event in; the buffer then
+//! belongs to the module. `tick` is called with a zero length. Nothing is
+//! passed on unless the module emits it, so dropping, cloning, modifying and
+//! constructing events are all done by emitting what should be sent on.
+//!
+//! The host provides, in the import module `cernan`,
+//!
+//! - `emit(ptr: i32, len: i32)`: send on the event serialized at `ptr`. Any
+//!   line accepted by `filter::parse_event` will do.
+//! - `state_get(key_ptr: i32, key_len: i32, buf_ptr: i32, buf_cap: i32) ->
+//!   i32`: copy the value of a state key into the buffer, returning its
+//!   length, or -1 if unset. Nothing is copied if the value is longer than
+//!   `buf_cap`.
+//! - `state_set(key_ptr: i32, key_len: i32, val_ptr: i32, val_len: i32)`
+//! - `state_incr(key_ptr: i32, key_len: i32, by: f64) -> f64`
+//!
+//! which mirror the `payload` library of the programmable filter. State is
+//! kept exactly as for programmable filters, under the `data-directory`.
+//!
+//! A module can touch no memory but its own and call no host function but
+//! those above. It can still run for as long as it likes, unless
+//! `instruction_limit` is set, and grow its memory up to the wasm32 maximum
+//! of 4GiB, unless the module itself declares a smaller maximum.
+//!
+//! With `instruction_limit` set the module is instrumented on load to count
+//! the instructions it runs, each call into it then failing with `instruction
+//! limit exceeded` once it has run that many. The count is kept per call, as
+//! the programmable filter's is, and the instrumentation imports `env.gas`,
+//! a name the module cannot then use itself.
+
+use filter::ErrorPolicy;
+use metric;
+use std::path::PathBuf;
+
+#[cfg(feature = "wasm")]
+use filter;
+#[cfg(feature = "wasm")]
+use filter::{parse_event, render_event};
+#[cfg(feature = "wasm")]
+use filter::state::{StateStore, StateValue, state_path};
+#[cfg(feature = "wasm")]
+use parity_wasm;
+#[cfg(feature = "wasm")]
+use pwasm_utils;
+#[cfg(feature = "wasm")]
+use std::fmt;
+#[cfg(feature = "wasm")]
+use std::fs;
+#[cfg(feature = "wasm")]
+use std::io::Read;
+#[cfg(feature = "wasm")]
+use std::sync;
+#[cfg(feature = "wasm")]
+use wasmi::{Error as WasmError, Externals, FuncInstance, FuncRef, HostError, ImportsBuilder,
+            MemoryRef, Module, ModuleImportResolver, ModuleInstance, ModuleRef, RuntimeArgs,
+            RuntimeValue, Signature, Trap, TrapKind, ValueType};
+
+#[derive(Debug, Clone)]
+pub struct WasmFilterConfig {
+    pub module: PathBuf,
+    pub on_error: ErrorPolicy,
+    pub data_directory: PathBuf,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub tags: metric::TagMap,
+    /// The number of wasm instructions one call into the module may run, by
+    /// default unlimited
+    pub instruction_limit: Option<u32>,
+}
+
+impl Default for WasmFilterConfig {
+    fn default() -> WasmFilterConfig {
+        WasmFilterConfig {
+            module: PathBuf::new(),
+            on_error: ErrorPolicy::PassThrough,
+            data_directory: PathBuf::from("/tmp/cernan-data"),
+            forwards: Vec::new(),
+            config_path: "filters.wasm".to_string(),
+            tags: Default::default(),
+            instruction_limit: None,
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+const ENTRY_POINTS: [&'static str; 3] = ["process_metric", "process_log", "tick"];
+
+#[cfg(feature = "wasm")]
+const EMIT: usize = 0;
+#[cfg(feature = "wasm")]
+const STATE_GET: usize = 1;
+#[cfg(feature = "wasm")]
+const STATE_SET: usize = 2;
+#[cfg(feature = "wasm")]
+const STATE_INCR: usize = 3;
+#[cfg(feature = "wasm")]
+const GAS: usize = 4;
+
+#[cfg(feature = "wasm")]
+const PTR_LEN: &'static [ValueType] = &[ValueType::I32, ValueType::I32];
+#[cfg(feature = "wasm")]
+const PTR_LEN_PTR_LEN: &'static [ValueType] =
+    &[ValueType::I32, ValueType::I32, ValueType::I32, ValueType::I32];
+#[cfg(feature = "wasm")]
+const PTR_LEN_F64: &'static [ValueType] = &[ValueType::I32, ValueType::I32, ValueType::F64];
+#[cfg(feature = "wasm")]
+const COST: &'static [ValueType] = &[ValueType::I32];
+
+/// Resolves the imports of the `cernan` module
+#[cfg(feature = "wasm")]
+struct HostResolver;
+
+#[cfg(feature = "wasm")]
+impl ModuleImportResolver for HostResolver {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, WasmError> {
+        let expected = match field_name {
+            "emit" => Signature::new(PTR_LEN, None),
+            "state_get" => Signature::new(PTR_LEN_PTR_LEN, Some(ValueType::I32)),
+            "state_set" => Signature::new(PTR_LEN_PTR_LEN, None),
+            "state_incr" => Signature::new(PTR_LEN_F64, Some(ValueType::F64)),
+            _ => {
+                return Err(WasmError::Instantiation(format!("no host function cernan.{}",
+                                                            field_name)))
+            }
+        };
+        if *signature != expected {
+            return Err(WasmError::Instantiation(format!("cernan.{} has signature {:?}, not \
+                                                         {:?}",
+                                                        field_name,
+                                                        signature,
+                                                        expected)));
+        }
+        let index = match field_name {
+            "emit" => EMIT,
+            "state_get" => STATE_GET,
+            "state_set" => STATE_SET,
+            _ => STATE_INCR,
+        };
+        Ok(FuncInstance::alloc_host(expected, index))
+    }
+}
+
+/// Resolves `env.gas`, which instrumented modules call with the number of
+/// instructions they are about to run
+#[cfg(feature = "wasm")]
+struct GasResolver;
+
+#[cfg(feature = "wasm")]
+impl ModuleImportResolver for GasResolver {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, WasmError> {
+        let expected = Signature::new(COST, None);
+        if field_name != "gas" || *signature != expected {
+            return Err(WasmError::Instantiation(format!("no host function env.{}", field_name)));
+        }
+        Ok(FuncInstance::alloc_host(expected, GAS))
+    }
+}
+
+/// Raised in a module that has run past its instruction limit
+#[cfg(feature = "wasm")]
+#[derive(Debug)]
+struct InstructionLimitExceeded;
+
+#[cfg(feature = "wasm")]
+impl fmt::Display for InstructionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "instruction limit exceeded")
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl HostError for InstructionLimitExceeded {}
+
+/// The host side of one call into a module
+#[cfg(feature = "wasm")]
+struct Host<'a> {
+    memory: MemoryRef,
+    events: Vec<metric::Event>,
+    store: &'a mut StateStore,
+    instructions_left: Option<u32>,
+}
+
+#[cfg(feature = "wasm")]
+impl<'a> Host<'a> {
+    fn read(&self, args: &RuntimeArgs, at: usize) -> Result<String, Trap> {
+        let ptr: u32 = args.nth_checked(at)?;
+        let len: u32 = args.nth_checked(at + 1)?;
+        match self.memory.get(ptr, len as usize) {
+            Ok(buf) => Ok(String::from_utf8_lossy(&buf).into_owned()),
+            Err(_) => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl<'a> Externals for Host<'a> {
+    fn invoke_index(&mut self,
+                    index: usize,
+                    args: RuntimeArgs)
+                    -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            EMIT => {
+                let line = self.read(&args, 0)?;
+                self.events.extend(parse_event(&line));
+                Ok(None)
+            }
+            STATE_GET => {
+                let key = self.read(&args, 0)?;
+                let ptr: u32 = args.nth_checked(2)?;
+                let cap: u32 = args.nth_checked(3)?;
+                let val = match self.store.get(&key) {
+                    Some(&StateValue::Number(n)) => n.to_string(),
+                    Some(&StateValue::Text(ref s)) => s.clone(),
+                    None => return Ok(Some(RuntimeValue::from(-1i32))),
+                };
+                if val.len() <= cap as usize && self.memory.set(ptr, val.as_bytes()).is_err() {
+                    return Err(Trap::new(TrapKind::MemoryAccessOutOfBounds));
+                }
+                Ok(Some(RuntimeValue::from(val.len() as i32)))
+            }
+            STATE_SET => {
+                let key = self.read(&args, 0)?;
+                let val = self.read(&args, 2)?;
+                self.store.set(key, Some(StateValue::Text(val)));
+                Ok(None)
+            }
+            STATE_INCR => {
+                let key = self.read(&args, 0)?;
+                let by: f64 = args.nth_checked(2)?;
+                Ok(Some(RuntimeValue::from(self.store.incr(key, by))))
+            }
+            GAS => {
+                let used: u32 = args.nth_checked(0)?;
+                if let Some(ref mut left) = self.instructions_left {
+                    if used > *left {
+                        return Err(Trap::from(InstructionLimitExceeded));
+                    }
+                    *left -= used;
+                }
+                Ok(None)
+            }
+            _ => Err(Trap::new(TrapKind::Unreachable)),
+        }
+    }
+}
+
+/// Describe a failed call, giving host errors in their own words rather than
+/// as the `Debug` of the trap wrapping them
+#[cfg(feature = "wasm")]
+fn describe(e: WasmError) -> String {
+    match e.as_host_error() {
+        Some(host) => host.to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// Run events through a WebAssembly module
+///
+/// See the module documentation for the interface a module must provide.
+/// Script failures are handled as in `ProgrammableFilter`, according to
+/// `on_error`, and counted in `cernan.filter.<config_path>.script_error`.
+#[cfg(feature = "wasm")]
+pub struct WasmFilter {
+    instance: ModuleRef,
+    memory: MemoryRef,
+    path: String,
+    global_tags: metric::TagMap,
+    on_error: ErrorPolicy,
+    errors: u64,
+    store: StateStore,
+    instruction_limit: Option<u32>,
+}
+
+#[cfg(feature = "wasm")]
+impl WasmFilter {
+    pub fn new(config: WasmFilterConfig) -> Result<WasmFilter, String> {
+        let mut buf = Vec::new();
+        if let Err(e) = fs::File::open(&config.module).and_then(|mut fp| fp.read_to_end(&mut buf)) {
+            return Err(format!("could not read {:?}: {}", config.module, e));
+        }
+        let module = parity_wasm::deserialize_buffer::<parity_wasm::elements::Module>(&buf)
+            .map_err(|e| format!("invalid module {:?}: {}", config.module, e))?;
+        let module = match config.instruction_limit {
+            Some(_) => {
+                pwasm_utils::inject_gas_counter(module, &pwasm_utils::rules::Set::default())
+                    .map_err(|_| format!("could not instrument {:?}", config.module))?
+            }
+            None => module,
+        };
+        let module = Module::from_parity_wasm_module(module)
+            .map_err(|e| format!("invalid module {:?}: {}", config.module, e))?;
+        let imports = ImportsBuilder::new()
+            .with_resolver("cernan", &HostResolver)
+            .with_resolver("env", &GasResolver);
+        let not_started = ModuleInstance::new(&module, &imports)
+            .map_err(|e| format!("could not instantiate {:?}: {}", config.module, e))?;
+
+        let memory = match not_started.not_started_instance()
+            .export_by_name("memory")
+            .and_then(|e| e.as_memory().cloned()) {
+            Some(memory) => memory,
+            None => return Err(format!("{:?} does not export memory", config.module)),
+        };
+
+        let mut store = StateStore::new(state_path(&config.data_directory, &config.config_path));
+        if let Err(e) = store.read() {
+            error!("unable to read state for {}: {}", config.config_path, e);
+        }
+
+        let instance = {
+            let mut host = Host {
+                memory: memory.clone(),
+                events: Vec::new(),
+                store: &mut store,
+                instructions_left: config.instruction_limit,
+            };
+            not_started.run_start(&mut host)
+                .map_err(|e| describe(WasmError::Trap(e)))
+                .map_err(|e| format!("could not instantiate {:?}: {}", config.module, e))?
+        };
+        for func in ENTRY_POINTS.iter().chain(&["cernan_alloc"]) {
+            if instance.export_by_name(func).and_then(|e| e.as_func().cloned()).is_none() {
+                return Err(format!("{:?} does not export {}", config.module, func));
+            }
+        }
+
+        Ok(WasmFilter {
+            instance: instance,
+            memory: memory,
+            path: config.config_path,
+            global_tags: config.tags,
+            on_error: config.on_error,
+            errors: 0,
+            store: store,
+            instruction_limit: config.instruction_limit,
+        })
+    }
+
+    /// Call the module's `func`, collecting any events it emits into
+    /// `events`
+    fn call(&mut self,
+            func: &str,
+            args: &[RuntimeValue],
+            events: &mut Vec<metric::Event>)
+            -> Result<Option<RuntimeValue>, String> {
+        let mut host = Host {
+            memory: self.memory.clone(),
+            events: Vec::new(),
+            store: &mut self.store,
+            instructions_left: self.instruction_limit,
+        };
+        let res = self.instance.invoke_export(func, args, &mut host).map_err(describe);
+        events.append(&mut host.events);
+        res
+    }
+
+    /// Copy `payload` into the module and call `func` on it, returning the
+    /// events the module emits
+    fn invoke(&mut self, func: &str, payload: &[u8]) -> Result<Vec<metric::Event>, String> {
+        let mut events = Vec::new();
+        let ptr = if payload.is_empty() {
+            0
+        } else {
+            let len = RuntimeValue::from(payload.len() as i32);
+            let ptr = match self.call("cernan_alloc", &[len], &mut events) {
+                Ok(Some(RuntimeValue::I32(ptr))) => ptr,
+                Ok(_) => return Err("cernan_alloc did not return a pointer".to_string()),
+                Err(e) => return Err(format!("cernan_alloc failed: {}", e)),
+            };
+            self.memory.set(ptr as u32, payload).map_err(|e| e.to_string())?;
+            ptr
+        };
+        let args = [RuntimeValue::from(ptr), RuntimeValue::from(payload.len() as i32)];
+        self.call(func, &args, &mut events)?;
+        Ok(events)
+    }
+
+    /// Report the script errors seen since the last flush, if any, and
+    /// persist state
+    fn flush(&mut self, res: &mut Vec<metric::Event>) {
+        if self.errors > 0 {
+            let errors = metric::Telemetry::new(format!("cernan.filter.{}.script_error",
+                                                        self.path),
+                                                self.errors as f64)
+                .aggr_sum()
+                .overlay_tags_from_map(&self.global_tags);
+            res.push(metric::Event::new_telemetry(errors));
+            self.errors = 0;
+        }
+        if let Err(e) = self.store.write() {
+            error!("unable to write state for {}: {}", self.path, e);
+        }
+    }
+
+    /// Fill in global tags the module did not set itself
+    fn with_global_tags(&self, event: metric::Event) -> metric::Event {
+        match event {
+            metric::Event::Telemetry(mut m) => {
+                let m = sync::Arc::make_mut(&mut m).take().unwrap();
+                metric::Event::new_telemetry(m.merge_tags_from_map(&self.global_tags))
+            }
+            metric::Event::Log(mut l) => {
+                let mut l = sync::Arc::make_mut(&mut l).take().unwrap();
+                l.tags.merge(&self.global_tags);
+                metric::Event::new_log(l)
+            }
+            metric::Event::TimerFlush => metric::Event::TimerFlush,
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl filter::Filter for WasmFilter {
+    fn process(&mut self,
+               event: metric::Event,
+               res: &mut Vec<metric::Event>)
+               -> Result<(), filter::FilterError> {
+        let func = match event {
+            metric::Event::Telemetry(_) => "process_metric",
+            metric::Event::Log(_) => "process_log",
+            metric::Event::TimerFlush => "tick",
+        };
+        let payload = render_event(&event).map_or_else(Vec::new, |line| line.into_bytes());
+        let pass_through = match event {
+            metric::Event::TimerFlush => Vec::new(),
+            _ if self.on_error == ErrorPolicy::PassThrough => vec![event],
+            _ => Vec::new(),
+        };
+
+        match self.invoke(func, &payload) {
+            Ok(events) => {
+                for event in events {
+                    res.push(self.with_global_tags(event));
+                }
+            }
+            Err(msg) => {
+                self.errors += 1;
+                let mut pass_through = pass_through;
+                if func == "tick" {
+                    // The flush happens all the same, its report going out
+                    // alongside the failure.
+                    self.flush(&mut pass_through);
+                }
+                return Err(filter::FilterError::ScriptError(format!("{} failed: {}", func, msg),
+                                                            pass_through));
+            }
+        }
+
+        if func == "tick" {
+            self.flush(res);
+        }
+        Ok(())
+    }
+}
@@ -30,15 +30,22 @@ extern crate hopper;
 extern crate hyper;
 extern crate libc;
 extern crate lua;
+#[cfg(feature = "wasm")]
+extern crate parity_wasm;
 extern crate protobuf;
+#[cfg(feature = "wasm")]
+extern crate pwasm_utils;
 extern crate quantiles;
 extern crate rand;
+extern crate regex;
 extern crate rusoto;
 extern crate seahash;
 extern crate serde;
 extern crate serde_json;
 extern crate toml;
 extern crate uuid;
+#[cfg(feature = "wasm")]
+extern crate wasmi;
 
 #[macro_use]
 extern crate log;
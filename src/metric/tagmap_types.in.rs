@@ -1,4 +1,4 @@
-#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,Serialize,Deserialize)]
 pub struct TagMap<K, V> {
     inner: Vec<(K, V)>,
 }
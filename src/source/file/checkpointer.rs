@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A file is identified by its device and inode, not its path. This way a
+/// checkpoint follows a file through a rename.
+pub type FileId = (u64, u64); // (dev, ino)
+
+/// Durable record of how far into each file the file source has read.
+///
+/// Checkpoints are kept in memory and flushed to disk on `write`. The on-disk
+/// format is one `dev ino offset` triple per line. Writes go to a temporary
+/// file which is then renamed over the old checkpoints so a crash mid-write
+/// never leaves a torn file behind.
+pub struct Checkpointer {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    checkpoints: HashMap<FileId, u64>,
+    dirty: bool,
+}
+
+impl Checkpointer {
+    pub fn new(path: PathBuf) -> Checkpointer {
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("tmp");
+        Checkpointer {
+            path: path,
+            tmp_path: tmp_path,
+            checkpoints: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Load checkpoints from disk, if any have been written
+    ///
+    /// A missing checkpoint file is not an error: it simply means this source
+    /// has never run before. Malformed lines are skipped.
+    pub fn read(&mut self) -> io::Result<()> {
+        let fp = match fs::File::open(&self.path) {
+            Ok(fp) => fp,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for line in io::BufReader::new(fp).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                warn!("malformed checkpoint in {:?}: {}", self.path, line);
+                continue;
+            }
+            match (u64::from_str(fields[0]), u64::from_str(fields[1]), u64::from_str(fields[2])) {
+                (Ok(dev), Ok(ino), Ok(pos)) => {
+                    self.checkpoints.insert((dev, ino), pos);
+                }
+                _ => warn!("malformed checkpoint in {:?}: {}", self.path, line),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, file_id: FileId) -> Option<u64> {
+        self.checkpoints.get(&file_id).cloned()
+    }
+
+    pub fn set(&mut self, file_id: FileId, pos: u64) {
+        if self.checkpoints.insert(file_id, pos) != Some(pos) {
+            self.dirty = true;
+        }
+    }
+
+    /// Drop checkpoints for every file not accepted by `keep`
+    pub fn retain<F>(&mut self, mut keep: F)
+        where F: FnMut(&FileId) -> bool
+    {
+        let dead: Vec<FileId> = self.checkpoints.keys().filter(|id| !keep(id)).cloned().collect();
+        for id in &dead {
+            self.checkpoints.remove(id);
+        }
+        if !dead.is_empty() {
+            self.dirty = true;
+        }
+    }
+
+    /// Persist checkpoints to disk, if they've changed since the last write
+    pub fn write(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        {
+            let mut fp = io::BufWriter::new(fs::File::create(&self.tmp_path)?);
+            for (&(dev, ino), pos) in &self.checkpoints {
+                writeln!(fp, "{} {} {}", dev, ino, pos)?;
+            }
+            fp.flush()?;
+            fp.get_ref().sync_all()?;
+        }
+        fs::rename(&self.tmp_path, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn checkpoints_survive_round_trip() {
+        let dir = TempDir::new("cernan_checkpoints").unwrap();
+        let path = dir.path().join("checkpoints");
+
+        let mut chkptr = Checkpointer::new(path.clone());
+        chkptr.set((1, 2), 1024);
+        chkptr.set((3, 4), 0);
+        chkptr.write().unwrap();
+
+        let mut restored = Checkpointer::new(path);
+        restored.read().unwrap();
+        assert_eq!(restored.get((1, 2)), Some(1024));
+        assert_eq!(restored.get((3, 4)), Some(0));
+        assert_eq!(restored.get((5, 6)), None);
+    }
+
+    #[test]
+    fn missing_checkpoint_file_is_empty() {
+        let dir = TempDir::new("cernan_checkpoints").unwrap();
+        let mut chkptr = Checkpointer::new(dir.path().join("never_written"));
+        assert!(chkptr.read().is_ok());
+        assert_eq!(chkptr.get((1, 2)), None);
+    }
+
+    #[test]
+    fn retain_drops_dead_files() {
+        let dir = TempDir::new("cernan_checkpoints").unwrap();
+        let path = dir.path().join("checkpoints");
+
+        let mut chkptr = Checkpointer::new(path.clone());
+        chkptr.set((1, 2), 10);
+        chkptr.set((3, 4), 20);
+        chkptr.retain(|id| *id == (3, 4));
+        chkptr.write().unwrap();
+
+        let mut restored = Checkpointer::new(path);
+        restored.read().unwrap();
+        assert_eq!(restored.get((1, 2)), None);
+        assert_eq!(restored.get((3, 4)), Some(20));
+    }
+}
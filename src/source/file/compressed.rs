@@ -0,0 +1,87 @@
+use flate2::read::GzDecoder;
+use source::file::checkpointer::FileId;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Is `path` a gzip compressed file?
+pub fn is_gzip(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "gz")
+}
+
+/// A gzip compressed file, read once from beginning to end.
+///
+/// Compressed files are presumed to be complete, typically logs that have
+/// been rotated and compressed. They are not tailed. A compressed file that
+/// is only partially read when cernan stops will be read again from the
+/// beginning.
+pub struct GzFile {
+    pub path: PathBuf,
+    pub file_id: FileId,
+    pub len: u64,
+    reader: io::BufReader<GzDecoder<fs::File>>,
+}
+
+impl GzFile {
+    pub fn new(path: PathBuf) -> io::Result<GzFile> {
+        let f = fs::File::open(&path)?;
+        let metadata = f.metadata()?;
+        let decoder = GzDecoder::new(f)?;
+        Ok(GzFile {
+            path: path,
+            file_id: (metadata.dev(), metadata.ino()),
+            len: metadata.len(),
+            reader: io::BufReader::new(decoder),
+        })
+    }
+
+    /// Read a single line into `buffer`, returning 0 once the file is
+    /// exhausted
+    pub fn read_line(&mut self, buffer: &mut Vec<u8>) -> io::Result<usize> {
+        self.reader.read_until(b'\n', buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use self::tempdir::TempDir;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use super::*;
+
+    #[test]
+    fn is_gzip_by_extension() {
+        assert!(is_gzip(Path::new("/var/log/syslog.2.gz")));
+        assert!(!is_gzip(Path::new("/var/log/syslog.1")));
+        assert!(!is_gzip(Path::new("/var/log/gz")));
+    }
+
+    #[test]
+    fn reads_to_completion() {
+        let dir = TempDir::new("cernan_gz").unwrap();
+        let path = dir.path().join("a.log.gz");
+        {
+            let fp = fs::File::create(&path).unwrap();
+            let mut enc = GzEncoder::new(fp, Compression::Default);
+            enc.write_all(b"first\nsecond\n").unwrap();
+            enc.finish().unwrap();
+        }
+
+        let mut gz = GzFile::new(path).unwrap();
+        let mut buffer = Vec::new();
+        assert_eq!(gz.read_line(&mut buffer).unwrap(), 6);
+        assert_eq!(buffer, b"first\n");
+        buffer.clear();
+        assert_eq!(gz.read_line(&mut buffer).unwrap(), 7);
+        assert_eq!(buffer, b"second\n");
+        buffer.clear();
+        assert_eq!(gz.read_line(&mut buffer).unwrap(), 0);
+    }
+}
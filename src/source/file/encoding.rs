@@ -0,0 +1,75 @@
+use std::str;
+
+/// How the bytes of a line are turned into a `LogLine` value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// UTF-8, with invalid sequences replaced by U+FFFD.
+    Utf8Lossy,
+    /// UTF-8, with lines containing invalid sequences dropped.
+    Utf8Strict,
+    /// ISO-8859-1. Every byte is a valid character.
+    Latin1,
+}
+
+impl Default for Encoding {
+    fn default() -> Encoding {
+        Encoding::Utf8Lossy
+    }
+}
+
+/// The result of decoding a line.
+#[derive(Debug, PartialEq)]
+pub enum Decoded {
+    /// The line was valid in the configured encoding.
+    Clean(String),
+    /// The line was not valid but has been repaired.
+    Repaired(String),
+    /// The line was not valid and has been dropped.
+    Undecodable,
+}
+
+impl Encoding {
+    pub fn decode(&self, bytes: &[u8]) -> Decoded {
+        match *self {
+            Encoding::Latin1 => Decoded::Clean(bytes.iter().map(|b| *b as char).collect()),
+            Encoding::Utf8Lossy => {
+                match str::from_utf8(bytes) {
+                    Ok(s) => Decoded::Clean(s.to_string()),
+                    Err(_) => Decoded::Repaired(String::from_utf8_lossy(bytes).into_owned()),
+                }
+            }
+            Encoding::Utf8Strict => {
+                match str::from_utf8(bytes) {
+                    Ok(s) => Decoded::Clean(s.to_string()),
+                    Err(_) => Decoded::Undecodable,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn utf8_lossy_repairs() {
+        assert_eq!(Encoding::Utf8Lossy.decode(b"caf\xc3\xa9"),
+                   Decoded::Clean("café".to_string()));
+        assert_eq!(Encoding::Utf8Lossy.decode(b"bad \xff byte"),
+                   Decoded::Repaired("bad \u{FFFD} byte".to_string()));
+    }
+
+    #[test]
+    fn utf8_strict_drops() {
+        assert_eq!(Encoding::Utf8Strict.decode(b"fine"),
+                   Decoded::Clean("fine".to_string()));
+        assert_eq!(Encoding::Utf8Strict.decode(b"bad \xff byte"), Decoded::Undecodable);
+    }
+
+    #[test]
+    fn latin1_always_decodes() {
+        assert_eq!(Encoding::Latin1.decode(b"caf\xe9"),
+                   Decoded::Clean("café".to_string()));
+    }
+}
@@ -0,0 +1,172 @@
+use source::file::StartPosition;
+use source::file::checkpointer::{Checkpointer, FileId};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The file watcher tails a single file, following it across rotation.
+///
+/// `pos` is the byte offset of the next unread byte in the file identified by
+/// `file_id`. It's this pair that the file server checkpoints.
+///
+/// Two styles of rotation are understood. In 'rename' rotation the file at
+/// `path` is moved aside and a new file created in its place. Writers that
+/// have not yet reopened their log will keep appending to the old file, so
+/// the watcher continues to drain it until it has been quiet for
+/// `rotation_grace` and only then switches to the new file. In
+/// 'copytruncate' rotation the file is copied aside and truncated in
+/// place. The inode does not change but the file shrinks below `pos`, in
+/// which case the watcher starts over from the beginning of the file.
+pub struct FileWatcher {
+    pub path: PathBuf,
+    pub reader: io::BufReader<fs::File>,
+    pub file_id: FileId,
+    pub pos: u64,
+    pub last_read: Instant,
+    rotation_grace: Duration,
+    rotated_at: Option<Instant>,
+}
+
+impl FileWatcher {
+    /// Open `path` and seek according to `start`
+    ///
+    /// When `start` is `StartPosition::Checkpoint` and the checkpointer has
+    /// an offset for this file the watcher resumes from there. If there is no
+    /// checkpoint, or the file is now shorter than the checkpoint claims, the
+    /// watcher starts at the end of the file as if `StartPosition::End` had
+    /// been configured.
+    pub fn new(path: PathBuf,
+               start: StartPosition,
+               checkpoints: &Checkpointer)
+               -> Option<FileWatcher> {
+        match fs::File::open(&path) {
+            Ok(f) => {
+                let metadata = match f.metadata() {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!("no metadata in FileWatcher::new : {:?} {}", &path, e);
+                        return None;
+                    }
+                };
+                let dev = metadata.dev();
+                let ino = metadata.ino();
+                let len = metadata.len();
+
+                let pos = match start {
+                    StartPosition::Beginning => 0,
+                    StartPosition::End => len,
+                    StartPosition::Checkpoint => {
+                        match checkpoints.get((dev, ino)) {
+                            Some(pos) if pos <= len => pos,
+                            _ => len,
+                        }
+                    }
+                };
+                let mut rdr = io::BufReader::new(f);
+                let pos = match rdr.seek(io::SeekFrom::Start(pos)) {
+                    Ok(pos) => pos,
+                    Err(e) => {
+                        warn!("unable to seek in {:?}: {}", &path, e);
+                        return None;
+                    }
+                };
+
+                Some(FileWatcher {
+                    path: path,
+                    reader: rdr,
+                    file_id: (dev, ino),
+                    pos: pos,
+                    rotation_grace: Duration::from_secs(5),
+                    rotated_at: None,
+                    last_read: Instant::now(),
+                })
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Adjust how long a rotated-away file is drained
+    ///
+    /// Once a rename rotation is noticed the watcher will keep reading the
+    /// old file until no new data has arrived in it for `grace`. The default
+    /// is five seconds.
+    pub fn rotation_grace(mut self, grace: Duration) -> FileWatcher {
+        self.rotation_grace = grace;
+        self
+    }
+
+    /// Move the read position to `pos` bytes into the file
+    pub fn seek(&mut self, pos: u64) -> io::Result<()> {
+        self.pos = self.reader.seek(io::SeekFrom::Start(pos))?;
+        Ok(())
+    }
+
+    /// Read a single line from the file into `buffer`
+    ///
+    /// The line is read as raw bytes, including the trailing newline. No
+    /// assumption is made about the file's encoding. This function does not
+    /// block waiting for input. When there is nothing to read an error is
+    /// returned and the caller is expected to try again later.
+    pub fn read_line(&mut self, buffer: &mut Vec<u8>) -> io::Result<usize> {
+        loop {
+            match self.reader.read_until(b'\n', buffer) {
+                Ok(0) => {}
+                Ok(sz) => {
+                    self.pos += sz as u64;
+                    self.last_read = Instant::now();
+                    if self.rotated_at.is_some() {
+                        // the old file is still being written to
+                        self.rotated_at = Some(Instant::now());
+                    }
+                    return Ok(sz);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+
+            // We're at EOF. Either there's nothing more to read just now or
+            // the file has been rotated out from under us.
+            let metadata = match fs::metadata(&self.path) {
+                Ok(metadata) => metadata,
+                // The file has been moved aside but no new file has been
+                // created yet. Keep the old one open; it's all we've got.
+                Err(_) => return Err(io::Error::last_os_error()),
+            };
+            let dev = metadata.dev();
+            let ino = metadata.ino();
+
+            if (dev, ino) != self.file_id {
+                let now = Instant::now();
+                let rotated_at = match self.rotated_at {
+                    Some(rotated_at) => rotated_at,
+                    None => {
+                        self.rotated_at = Some(now);
+                        now
+                    }
+                };
+                if now.duration_since(rotated_at) < self.rotation_grace {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Ok(f) = fs::File::open(&self.path) {
+                    self.file_id = (dev, ino);
+                    let rdr = io::BufReader::new(f);
+                    self.reader = rdr;
+                    self.pos = 0;
+                    self.rotated_at = None;
+                } else {
+                    return Err(io::Error::last_os_error());
+                }
+            } else if metadata.len() < self.pos {
+                // copytruncate rotation
+                self.reader.seek(io::SeekFrom::Start(0))?;
+                self.pos = 0;
+            } else {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+}
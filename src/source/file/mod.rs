@@ -0,0 +1,725 @@
+use glob::{Pattern, glob};
+use metric;
+use seahash::SeaHasher;
+use source::Source;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasherDefault;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::time::Instant;
+use time;
+use util;
+use util::send;
+
+mod checkpointer;
+mod compressed;
+mod encoding;
+mod file_watcher;
+mod multiline;
+mod notify;
+
+pub use self::checkpointer::{Checkpointer, FileId};
+use self::compressed::GzFile;
+use self::encoding::Decoded;
+pub use self::encoding::Encoding;
+pub use self::file_watcher::FileWatcher;
+use self::multiline::Multiline;
+pub use self::multiline::MultilineConfig;
+use self::notify::Notification;
+
+type HashMapFnv<K, V> = HashMap<K, V, BuildHasherDefault<SeaHasher>>;
+
+/// Where in a file found at start-up the file server begins reading.
+///
+/// Files that appear after start-up are always read from the beginning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartPosition {
+    /// Read the file from its first byte.
+    Beginning,
+    /// Read only lines written after the file is discovered.
+    End,
+    /// Resume from the last checkpointed offset, falling back to `End` for
+    /// files with no checkpoint.
+    Checkpoint,
+}
+
+impl Default for StartPosition {
+    fn default() -> StartPosition {
+        StartPosition::End
+    }
+}
+
+pub struct FileServer {
+    chans: util::Channel,
+    path: PathBuf,
+    start_position: StartPosition,
+    checkpointer: Checkpointer,
+    max_open_files: usize,
+    idle_timeout: Option<Duration>,
+    builder: LineBuilder,
+    fp_map: HashMapFnv<PathBuf, FileWatcher>,
+    gz_map: HashMapFnv<PathBuf, GzFile>,
+    idle: HashMapFnv<PathBuf, FileId>,
+    finished: HashMapFnv<PathBuf, FileId>,
+}
+
+#[derive(Debug)]
+pub struct FileServerConfig {
+    pub path: PathBuf,
+    pub tags: metric::TagMap,
+    pub forwards: Vec<String>,
+    pub config_path: String,
+    pub start_position: StartPosition,
+    pub data_directory: PathBuf,
+    pub multiline: Option<MultilineConfig>,
+    pub max_open_files: usize,
+    pub idle_timeout: Option<Duration>,
+    pub encoding: Encoding,
+}
+
+/// Turns the raw bytes read from files into `LogLine`s.
+///
+/// Lines held back by multi-line assembly have not been sent on, so the
+/// offset of the first of them, per path, is kept in `pending_start`. It's
+/// there, not at the read position, that a file is checkpointed.
+struct LineBuilder {
+    tags: metric::TagMap,
+    encoding: Encoding,
+    multiline: Option<MultilineConfig>,
+    ml_map: HashMapFnv<PathBuf, Multiline>,
+    pending_start: HashMapFnv<PathBuf, (FileId, u64)>,
+    undecodable: usize,
+}
+
+impl LineBuilder {
+    fn log_line(&self, path: &Path, value: &str) -> metric::LogLine {
+        metric::LogLine::new(path.to_str().expect("could not make path_name"), value)
+            .overlay_tags_from_map(&self.tags)
+    }
+
+    /// Decode a line read from `path`, pushing any completed `LogLine` onto
+    /// `lines`
+    ///
+    /// `start` is the file and offset the line was read from.
+    fn push(&mut self,
+            path: &Path,
+            start: (FileId, u64),
+            mut bytes: &[u8],
+            lines: &mut Vec<metric::LogLine>) {
+        if bytes.last() == Some(&b'\n') {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+        let value = match self.encoding.decode(bytes) {
+            Decoded::Clean(value) => value,
+            Decoded::Repaired(value) => {
+                self.undecodable += 1;
+                value
+            }
+            Decoded::Undecodable => {
+                self.undecodable += 1;
+                trace!("{:?} | undecodable line of {} bytes", path, bytes.len());
+                return;
+            }
+        };
+        trace!("{:?} | {}", path, value);
+        let value = match self.multiline {
+            Some(ref config) => {
+                let ml = self.ml_map
+                    .entry(path.to_path_buf())
+                    .or_insert_with(Multiline::default);
+                let was_pending = ml.is_pending();
+                let value = ml.push(config, &value);
+                // this line began the pending event
+                if !was_pending || value.is_some() {
+                    self.pending_start.insert(path.to_path_buf(), start);
+                }
+                value
+            }
+            None => Some(value),
+        };
+        if let Some(value) = value {
+            lines.push(self.log_line(path, &value));
+        }
+    }
+
+    /// Push the pending multi-line event for `path`, if it has gone stale or
+    /// `force` is set
+    fn expire(&mut self, path: &Path, force: bool, lines: &mut Vec<metric::LogLine>) {
+        let value = match self.multiline {
+            Some(ref config) => {
+                match self.ml_map.get_mut(path) {
+                    Some(ml) => if force { ml.flush() } else { ml.expire(config) },
+                    None => None,
+                }
+            }
+            None => None,
+        };
+        if force {
+            self.ml_map.remove(path);
+            self.pending_start.remove(path);
+        }
+        if let Some(value) = value {
+            self.pending_start.remove(path);
+            lines.push(self.log_line(path, &value));
+        }
+    }
+
+    /// The offset in `file_id` up to which lines read from `path` have been
+    /// sent on, given that `pos` has been read
+    fn committed(&self, path: &Path, file_id: FileId, pos: u64) -> u64 {
+        match self.pending_start.get(path) {
+            Some(&(id, start)) if id == file_id && start < pos => start,
+            _ => pos,
+        }
+    }
+
+    /// Telemetry for lines that could not be cleanly decoded since the last
+    /// call
+    fn undecodable_telemetry(&mut self) -> Option<metric::Telemetry> {
+        if self.undecodable == 0 {
+            return None;
+        }
+        let count = self.undecodable as f64;
+        self.undecodable = 0;
+        Some(metric::Telemetry::new("cernan.file.undecodable_line", count)
+            .aggr_sum()
+            .overlay_tags_from_map(&self.tags))
+    }
+}
+
+/// Compute the location of a file server's checkpoints
+///
+/// Each file server gets its own checkpoint file under
+/// `<data_directory>/file_checkpoints`, named for its config path. Config
+/// paths embed the glob being watched so anything that's not safe in a file
+/// name is replaced.
+fn checkpoint_path(data_directory: &Path, config_path: &str) -> PathBuf {
+    let name: String = config_path.chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' {
+            c
+        } else {
+            '_'
+        })
+        .collect();
+    data_directory.join("file_checkpoints").join(name)
+}
+
+impl FileServer {
+    pub fn new(chans: util::Channel, config: FileServerConfig) -> FileServer {
+        let mut checkpointer =
+            Checkpointer::new(checkpoint_path(&config.data_directory, &config.config_path));
+        if let Err(e) = checkpointer.read() {
+            error!("unable to read checkpoints for {}: {}", config.config_path, e);
+        }
+        FileServer {
+            chans: chans,
+            path: config.path,
+            start_position: config.start_position,
+            checkpointer: checkpointer,
+            max_open_files: config.max_open_files,
+            idle_timeout: config.idle_timeout,
+            builder: LineBuilder {
+                tags: config.tags,
+                encoding: config.encoding,
+                multiline: config.multiline,
+                ml_map: HashMapFnv::default(),
+                pending_start: HashMapFnv::default(),
+                undecodable: 0,
+            },
+            fp_map: HashMapFnv::default(),
+            gz_map: HashMapFnv::default(),
+            idle: HashMapFnv::default(),
+            finished: HashMapFnv::default(),
+        }
+    }
+
+    /// Glob for files, opening any we are not already watching
+    ///
+    /// On the first pass files are opened according to the configured
+    /// `StartPosition`. Files found later are new since start-up and are read
+    /// from the beginning. Files closed for being idle stay closed unless
+    /// they are in `wake`, in which case they resume where they left off.
+    ///
+    /// Compressed files are read once, start to finish. A compressed file
+    /// present at start-up is only read if the `StartPosition` is
+    /// `Beginning`, or is `Checkpoint` and the file was incompletely read
+    /// before.
+    fn discover(&mut self, initial: bool, wake: &HashSet<PathBuf>) {
+        let mut seen = HashSet::new();
+        for entry in glob(self.path.to_str().expect("no ability to glob"))
+            .expect("Failed to read glob pattern") {
+            match entry {
+                Ok(path) => {
+                    let path = notify::normalize(&path);
+                    seen.insert(path.clone());
+                    if self.fp_map.contains_key(&path) || self.gz_map.contains_key(&path) ||
+                       self.finished.contains_key(&path) {
+                        continue;
+                    }
+                    if self.idle.contains_key(&path) && !wake.contains(&path) {
+                        continue;
+                    }
+                    if self.fp_map.len() + self.gz_map.len() >= self.max_open_files {
+                        debug!("at max_open_files ({}), not opening {:?}",
+                               self.max_open_files,
+                               path);
+                        continue;
+                    }
+                    let start = if initial {
+                        self.start_position
+                    } else {
+                        StartPosition::Beginning
+                    };
+                    if compressed::is_gzip(&path) {
+                        self.discover_gzip(path, start);
+                        continue;
+                    }
+                    let fw = FileWatcher::new(path.clone(), start, &self.checkpointer);
+                    if let Some(mut fw) = fw {
+                        if let Some(file_id) = self.idle.remove(&path) {
+                            if file_id == fw.file_id {
+                                if let Some(pos) = self.checkpointer.get(file_id) {
+                                    if let Err(e) = fw.seek(pos) {
+                                        warn!("unable to resume {:?}: {}", path, e);
+                                    }
+                                }
+                            }
+                        }
+                        self.fp_map.insert(path, fw);
+                    };
+                }
+                Err(e) => {
+                    debug!("glob error: {}", e);
+                }
+            }
+        }
+        let gone: Vec<PathBuf> = self.idle
+            .keys()
+            .chain(self.finished.keys())
+            .filter(|path| !seen.contains(*path))
+            .cloned()
+            .collect();
+        for path in gone {
+            self.idle.remove(&path);
+            self.finished.remove(&path);
+        }
+    }
+
+    fn discover_gzip(&mut self, path: PathBuf, start: StartPosition) {
+        let gz = match GzFile::new(path.clone()) {
+            Ok(gz) => gz,
+            Err(e) => {
+                warn!("unable to open compressed file {:?}: {}", path, e);
+                return;
+            }
+        };
+        let done = self.checkpointer.get(gz.file_id) == Some(gz.len);
+        let skip = match start {
+            StartPosition::Beginning => done,
+            StartPosition::End => true,
+            StartPosition::Checkpoint => done || self.checkpointer.get(gz.file_id).is_none(),
+        };
+        if skip {
+            self.checkpointer.set(gz.file_id, gz.len);
+            self.finished.insert(path, gz.file_id);
+        } else {
+            // mark the file as in progress, to be read again from the
+            // beginning should we stop before finishing
+            self.checkpointer.set(gz.file_id, 0);
+            self.gz_map.insert(path, gz);
+        }
+    }
+
+    /// Read lines from watched files and send them on
+    ///
+    /// If `ready` is given only those files are read, else every file
+    /// is. Compressed files are always read. Returns the total number of
+    /// lines read.
+    fn read(&mut self, ready: Option<&HashSet<PathBuf>>) -> usize {
+        let mut buffer = Vec::new();
+        let mut lines = Vec::new();
+        let mut total_lines_read = 0;
+        for file in self.fp_map.values_mut() {
+            if let Some(ready) = ready {
+                if !ready.contains(&file.path) {
+                    continue;
+                }
+            }
+            let mut lines_read = 0;
+            loop {
+                match file.read_line(&mut buffer) {
+                    Ok(sz) => {
+                        if sz > 0 {
+                            lines_read += 1;
+                            let start = (file.file_id, file.pos - sz as u64);
+                            self.builder.push(&file.path, start, &buffer, &mut lines);
+                            buffer.clear();
+                            if lines_read > 10_000 {
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        match e.kind() {
+                            io::ErrorKind::TimedOut => {}
+                            _ => trace!("read-line error: {}", e),
+                        }
+                        buffer.clear();
+                        break;
+                    }
+                }
+            }
+            total_lines_read += lines_read;
+            self.builder.expire(&file.path, false, &mut lines);
+            for l in lines.drain(..) {
+                send("file", &mut self.chans, metric::Event::new_log(l));
+            }
+        }
+
+        let mut done = Vec::new();
+        for gz in self.gz_map.values_mut() {
+            let mut lines_read = 0;
+            while lines_read <= 10_000 {
+                match gz.read_line(&mut buffer) {
+                    Ok(0) => {
+                        done.push(gz.path.clone());
+                        break;
+                    }
+                    Ok(_) => {
+                        lines_read += 1;
+                        // compressed files are only checkpointed once done
+                        self.builder.push(&gz.path, (gz.file_id, 0), &buffer, &mut lines);
+                        buffer.clear();
+                    }
+                    Err(e) => {
+                        warn!("error reading compressed file {:?}, abandoning: {}", gz.path, e);
+                        done.push(gz.path.clone());
+                        buffer.clear();
+                        break;
+                    }
+                }
+            }
+            total_lines_read += lines_read;
+            for l in lines.drain(..) {
+                send("file", &mut self.chans, metric::Event::new_log(l));
+            }
+        }
+        for path in done {
+            let gz = self.gz_map.remove(&path).expect("finished file not in gz_map");
+            self.builder.expire(&path, true, &mut lines);
+            self.checkpointer.set(gz.file_id, gz.len);
+            self.finished.insert(path, gz.file_id);
+        }
+        for l in lines.drain(..) {
+            send("file", &mut self.chans, metric::Event::new_log(l));
+        }
+
+        if let Some(telem) = self.builder.undecodable_telemetry() {
+            send("file", &mut self.chans, metric::Event::new_telemetry(telem));
+        }
+        total_lines_read
+    }
+
+    /// Close files which have had nothing to read for longer than
+    /// `idle_timeout`, returning true if any were closed
+    fn close_idle(&mut self) -> bool {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return false,
+        };
+        let idle: Vec<PathBuf> = self.fp_map
+            .values()
+            .filter(|file| file.last_read.elapsed() >= idle_timeout)
+            .map(|file| file.path.clone())
+            .collect();
+        let mut lines = Vec::new();
+        for path in &idle {
+            let file = self.fp_map.remove(path).expect("idle file not in fp_map");
+            debug!("closing idle file {:?}", path);
+            self.checkpointer.set(file.file_id, file.pos);
+            self.idle.insert(path.clone(), file.file_id);
+            self.builder.expire(path, true, &mut lines);
+        }
+        for l in lines {
+            send("file", &mut self.chans, metric::Event::new_log(l));
+        }
+        !idle.is_empty()
+    }
+
+    /// Idle files that have been written to, or replaced, since they were
+    /// closed
+    ///
+    /// Used when polling, where there are no notifications to say so.
+    fn grown_idle(&self) -> HashSet<PathBuf> {
+        self.idle
+            .iter()
+            .filter(|&(path, file_id)| match fs::metadata(path) {
+                Ok(metadata) => {
+                    (metadata.dev(), metadata.ino()) != *file_id ||
+                    Some(metadata.len()) != self.checkpointer.get(*file_id)
+                }
+                Err(_) => false,
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    fn checkpoint(&mut self) {
+        for file in self.fp_map.values() {
+            let pos = self.builder.committed(&file.path, file.file_id, file.pos);
+            self.checkpointer.set(file.file_id, pos);
+        }
+        let fp_map = &self.fp_map;
+        let gz_map = &self.gz_map;
+        let idle = &self.idle;
+        let finished = &self.finished;
+        self.checkpointer.retain(|id| {
+            fp_map.values().any(|file| file.file_id == *id) ||
+            gz_map.values().any(|gz| gz.file_id == *id) || idle.values().any(|i| i == id) ||
+            finished.values().any(|i| i == id)
+        });
+        if let Err(e) = self.checkpointer.write() {
+            error!("unable to write checkpoints: {}", e);
+        }
+    }
+}
+
+impl Source for FileServer {
+    /// Tail every file matching the configured glob
+    ///
+    /// Where possible the directory holding the files is watched with
+    /// inotify: new files are picked up as soon as they're created and only
+    /// files that have been written to are read. Every `sweep_delay` all files
+    /// are read regardless, to catch writes to files which have been rotated
+    /// out of the watched directory. Where inotify isn't available every file
+    /// is polled, backing off while there is nothing to read.
+    fn run(&mut self) {
+        let glob_delay = Duration::from_secs(60);
+        let sweep_delay = Duration::from_secs(1);
+        let checkpoint_delay = Duration::from_secs(1);
+
+        let pattern = Pattern::new(notify::normalize(&self.path)
+                .to_str()
+                .expect("no ability to glob"))
+            .expect("Failed to read glob pattern");
+        let mut notifier = notify::notifier_for(&self.path);
+        let mut attempts = 0;
+        let mut need_glob = false;
+        let mut need_sweep = false;
+        let mut last_glob = Instant::now();
+        let mut last_sweep = Instant::now();
+        let mut last_checkpoint = Instant::now();
+
+        self.discover(true, &HashSet::new());
+        loop {
+            let mut ready = HashSet::new();
+            let mut wake = HashSet::new();
+            let mut notify_failed = false;
+            match notifier {
+                Some(ref mut notifier) => {
+                    match notifier.wait(sweep_delay) {
+                        Ok(notes) => {
+                            for note in notes {
+                                match note {
+                                    Notification::Created(path) => {
+                                        need_glob = need_glob || pattern.matches_path(&path);
+                                    }
+                                    Notification::Modified(path) => {
+                                        // an idle file we closed has come back to life
+                                        if self.idle.contains_key(&path) {
+                                            wake.insert(path.clone());
+                                            need_glob = true;
+                                        }
+                                        ready.insert(path);
+                                    }
+                                    Notification::Overflow => {
+                                        need_glob = true;
+                                        need_sweep = true;
+                                    }
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("file notification failed, falling back to polling: {}", e);
+                            notify_failed = true;
+                        }
+                    }
+                }
+                None => time::delay(attempts),
+            }
+            if notify_failed {
+                notifier = None;
+            }
+
+            if need_glob || last_glob.elapsed() >= glob_delay {
+                if notifier.is_none() {
+                    wake = self.grown_idle();
+                }
+                self.discover(false, &wake);
+                need_glob = false;
+                last_glob = Instant::now();
+            }
+
+            let lines_read = if notifier.is_none() || need_sweep ||
+                                last_sweep.elapsed() >= sweep_delay {
+                need_sweep = false;
+                last_sweep = Instant::now();
+                self.read(None)
+            } else {
+                self.read(Some(&ready))
+            };
+            attempts = if lines_read > 0 {
+                0
+            } else {
+                attempts.saturating_add(1)
+            };
+
+            // closing files may leave room under max_open_files for files
+            // passed over before
+            if self.close_idle() {
+                need_glob = true;
+            }
+            if last_checkpoint.elapsed() >= checkpoint_delay {
+                self.checkpoint();
+                last_checkpoint = Instant::now();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use regex::Regex;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::Duration;
+    use super::*;
+
+    fn file_server(dir: &Path, max_open_files: usize) -> FileServer {
+        FileServer::new(Vec::new(),
+                        FileServerConfig {
+                            path: dir.join("*.log"),
+                            tags: Default::default(),
+                            forwards: Vec::new(),
+                            config_path: "sources.files.test".to_string(),
+                            start_position: StartPosition::Beginning,
+                            data_directory: dir.join("data"),
+                            multiline: None,
+                            max_open_files: max_open_files,
+                            idle_timeout: Some(Duration::from_secs(0)),
+                            encoding: Default::default(),
+                        })
+    }
+
+    #[test]
+    fn max_open_files_caps_open_files() {
+        let dir = TempDir::new("cernan_file_server").unwrap();
+        for name in &["a.log", "b.log", "c.log"] {
+            fs::File::create(dir.path().join(name)).unwrap();
+        }
+        let mut server = file_server(dir.path(), 2);
+
+        server.discover(true, &HashSet::new());
+        assert_eq!(server.fp_map.len(), 2);
+
+        // closing the idle files makes room for the one passed over, and
+        // only that one
+        assert!(server.close_idle());
+        assert_eq!(server.idle.len(), 2);
+        server.discover(false, &HashSet::new());
+        assert_eq!(server.fp_map.len(), 1);
+        assert!(server.fp_map.keys().all(|path| !server.idle.contains_key(path)));
+    }
+
+    #[test]
+    fn idle_files_stay_closed_until_woken() {
+        let dir = TempDir::new("cernan_file_server").unwrap();
+        let path = dir.path().join("a.log");
+        let mut fp = fs::File::create(&path).unwrap();
+        writeln!(fp, "first").unwrap();
+        let mut server = file_server(dir.path(), 8);
+
+        server.discover(true, &HashSet::new());
+        assert_eq!(server.read(None), 1);
+        let pos = server.fp_map[&path].pos;
+        assert!(server.close_idle());
+        assert!(server.fp_map.is_empty());
+
+        server.discover(false, &HashSet::new());
+        assert!(server.fp_map.is_empty());
+        assert!(server.grown_idle().is_empty());
+
+        writeln!(fp, "second").unwrap();
+        let wake = server.grown_idle();
+        assert!(wake.contains(&path));
+        server.discover(false, &wake);
+        assert_eq!(server.fp_map[&path].pos, pos);
+        assert!(server.idle.is_empty());
+        assert_eq!(server.read(None), 1);
+    }
+
+    #[test]
+    fn checkpoint_holds_back_pending_multiline_event() {
+        let dir = TempDir::new("cernan_file_server").unwrap();
+        let path = dir.path().join("a.log");
+        let mut fp = fs::File::create(&path).unwrap();
+        write!(fp, "1 first\n  continued\n2 second\n").unwrap();
+        let mut server = file_server(dir.path(), 8);
+        server.builder.multiline = Some(MultilineConfig {
+            start_pattern: Some(Regex::new(r"^\d").unwrap()),
+            continuation_pattern: None,
+            flush_timeout: Duration::from_secs(60),
+        });
+
+        server.discover(true, &HashSet::new());
+        assert_eq!(server.read(None), 3);
+        let file_id = server.fp_map[&path].file_id;
+
+        // "2 second" is still pending, so a restart must read it again
+        server.checkpoint();
+        assert_eq!(server.checkpointer.get(file_id), Some(20));
+
+        writeln!(fp, "3 third").unwrap();
+        assert_eq!(server.read(None), 1);
+        server.checkpoint();
+        assert_eq!(server.checkpointer.get(file_id), Some(29));
+    }
+
+    #[test]
+    fn restart_rereads_pending_multiline_event() {
+        let dir = TempDir::new("cernan_file_server").unwrap();
+        let path = dir.path().join("a.log");
+        let mut fp = fs::File::create(&path).unwrap();
+        write!(fp, "1 first\n  continued\n2 second\n").unwrap();
+        let multiline = MultilineConfig {
+            start_pattern: Some(Regex::new(r"^\d").unwrap()),
+            continuation_pattern: None,
+            flush_timeout: Duration::from_secs(60),
+        };
+        {
+            let mut server = file_server(dir.path(), 8);
+            server.builder.multiline = Some(multiline.clone());
+            server.discover(true, &HashSet::new());
+            assert_eq!(server.read(None), 3);
+            server.checkpoint();
+        }
+        writeln!(fp, "3 third").unwrap();
+
+        let mut server = file_server(dir.path(), 8);
+        server.start_position = StartPosition::Checkpoint;
+        server.builder.multiline = Some(multiline);
+        server.discover(true, &HashSet::new());
+        // "2 second" was pending when checkpointed, so it's read again along
+        // with what was written while stopped, and nothing before it is
+        assert_eq!(server.fp_map[&path].pos, 20);
+        assert_eq!(server.read(None), 2);
+    }
+}
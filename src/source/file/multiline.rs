@@ -0,0 +1,148 @@
+use regex::Regex;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// Rules for assembling several physical lines into one logical log line.
+///
+/// A line continues the pending event if it matches `continuation_pattern` or
+/// fails to match `start_pattern`. Any other line begins a new event,
+/// emitting whatever was pending. An event with no new lines for
+/// `flush_timeout` is emitted as-is.
+#[derive(Debug, Clone)]
+pub struct MultilineConfig {
+    pub start_pattern: Option<Regex>,
+    pub continuation_pattern: Option<Regex>,
+    pub flush_timeout: Duration,
+}
+
+impl MultilineConfig {
+    fn continues(&self, line: &str) -> bool {
+        if let Some(ref cont) = self.continuation_pattern {
+            if cont.is_match(line) {
+                return true;
+            }
+        }
+        match self.start_pattern {
+            Some(ref start) => !start.is_match(line),
+            None => false,
+        }
+    }
+}
+
+/// Multi-line assembly state for a single file.
+pub struct Multiline {
+    pending: Option<String>,
+    last_line: Instant,
+}
+
+impl Default for Multiline {
+    fn default() -> Multiline {
+        Multiline {
+            pending: None,
+            last_line: Instant::now(),
+        }
+    }
+}
+
+impl Multiline {
+    /// Feed a line into the assembler
+    ///
+    /// If `line` begins a new event the previously pending event, if any, is
+    /// complete and returned.
+    pub fn push(&mut self, config: &MultilineConfig, line: &str) -> Option<String> {
+        self.last_line = Instant::now();
+        if let Some(ref mut pending) = self.pending {
+            if config.continues(line) {
+                pending.push('\n');
+                pending.push_str(line);
+                return None;
+            }
+        }
+        mem::replace(&mut self.pending, Some(line.to_string()))
+    }
+
+    /// Whether lines are being held back awaiting the rest of their event
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Emit the pending event regardless of its age
+    pub fn flush(&mut self) -> Option<String> {
+        self.pending.take()
+    }
+
+    /// Emit the pending event if it has gone stale
+    pub fn expire(&mut self, config: &MultilineConfig) -> Option<String> {
+        if self.pending.is_some() && self.last_line.elapsed() >= config.flush_timeout {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+    use std::time::Duration;
+    use super::*;
+
+    fn java_config() -> MultilineConfig {
+        MultilineConfig {
+            start_pattern: Some(Regex::new(r"^\d{4}-\d{2}-\d{2}").unwrap()),
+            continuation_pattern: None,
+            flush_timeout: Duration::from_millis(0),
+        }
+    }
+
+    fn python_config() -> MultilineConfig {
+        MultilineConfig {
+            start_pattern: None,
+            continuation_pattern: Some(Regex::new(r"^(\s|Traceback|\w+Error)").unwrap()),
+            flush_timeout: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn start_pattern_assembles_stack_trace() {
+        let config = java_config();
+        let mut ml = Multiline::default();
+
+        assert_eq!(ml.push(&config, "2017-03-01 ERROR boom"), None);
+        assert_eq!(ml.push(&config, "java.lang.NullPointerException"), None);
+        assert_eq!(ml.push(&config, "    at Foo.bar(Foo.java:10)"), None);
+        assert_eq!(ml.push(&config, "2017-03-01 INFO fine"),
+                   Some("2017-03-01 ERROR boom\njava.lang.NullPointerException\n    at \
+                         Foo.bar(Foo.java:10)"
+                       .to_string()));
+        assert_eq!(ml.expire(&config), Some("2017-03-01 INFO fine".to_string()));
+        assert_eq!(ml.expire(&config), None);
+    }
+
+    #[test]
+    fn continuation_pattern_assembles_traceback() {
+        let config = python_config();
+        let mut ml = Multiline::default();
+
+        assert_eq!(ml.push(&config, "request failed"), None);
+        assert_eq!(ml.push(&config, "Traceback (most recent call last):"), None);
+        assert_eq!(ml.push(&config, "  File \"app.py\", line 1, in <module>"), None);
+        assert_eq!(ml.push(&config, "KeyError: 'foo'"), None);
+        assert_eq!(ml.push(&config, "next request"),
+                   Some("request failed\nTraceback (most recent call last):\n  File \
+                         \"app.py\", line 1, in <module>\nKeyError: 'foo'"
+                       .to_string()));
+    }
+
+    #[test]
+    fn pending_event_waits_for_timeout() {
+        let config = python_config();
+        let mut ml = Multiline::default();
+
+        assert_eq!(ml.push(&config, "request failed"), None);
+        assert_eq!(ml.expire(&config), None);
+        assert!(ml.is_pending());
+        assert_eq!(ml.flush(), Some("request failed".to_string()));
+        assert!(!ml.is_pending());
+    }
+}
@@ -0,0 +1,288 @@
+//! File system notification for the file source.
+//!
+//! On Linux this is a thin wrapper around inotify watching the directory a
+//! file server's glob lives in. Elsewhere `Notifier::new` always fails and the
+//! file server falls back to polling.
+use std::path::{Component, Path, PathBuf};
+
+/// Something happened in a watched directory.
+#[derive(Debug, PartialEq)]
+pub enum Notification {
+    /// A file was created in, or moved into, the directory.
+    Created(PathBuf),
+    /// A file in the directory was written to.
+    Modified(PathBuf),
+    /// The kernel dropped events. Anything may have happened.
+    Overflow,
+}
+
+/// Determine the directory to watch for a file server glob
+///
+/// Only the final component of the glob may contain wildcards. Globs like
+/// `/var/log/*/app.log` can't be served by a single non-recursive watch and
+/// get `None`, meaning the file server must poll.
+pub fn watch_directory(glob: &Path) -> Option<PathBuf> {
+    match glob.parent().and_then(|p| p.to_str()) {
+        Some(parent) => {
+            if parent.contains(|c| "*?[]{}".contains(c)) {
+                None
+            } else if parent.is_empty() {
+                Some(PathBuf::from("."))
+            } else {
+                Some(PathBuf::from(parent))
+            }
+        }
+        None => None,
+    }
+}
+
+/// Drop `.` components from `path`
+///
+/// A watch on `.` reports `./a.log` where glob yields `a.log`. Both sides
+/// are passed through here so that they compare equal.
+pub fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use libc;
+    use libc::{c_char, c_int, c_void};
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+    use std::time::Duration;
+    use super::{Notification, normalize};
+
+    const IN_MODIFY: u32 = 0x0000_0002;
+    const IN_MOVED_TO: u32 = 0x0000_0080;
+    const IN_CREATE: u32 = 0x0000_0100;
+    const IN_Q_OVERFLOW: u32 = 0x0000_4000;
+
+    // struct inotify_event { int wd; uint32_t mask, cookie, len; char name[]; }
+    const EVENT_HEADER_SIZE: usize = 16;
+
+    extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    }
+
+    pub struct Notifier {
+        fd: c_int,
+        watches: HashMap<c_int, PathBuf>,
+        buf: Vec<u8>,
+    }
+
+    impl Notifier {
+        pub fn new() -> io::Result<Notifier> {
+            let fd = unsafe { inotify_init1(libc::O_NONBLOCK | libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Notifier {
+                fd: fd,
+                watches: HashMap::new(),
+                buf: vec![0; 64 * 1024],
+            })
+        }
+
+        pub fn watch(&mut self, dir: &Path) -> io::Result<()> {
+            let cpath = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let wd = unsafe {
+                inotify_add_watch(self.fd, cpath.as_ptr(), IN_MODIFY | IN_MOVED_TO | IN_CREATE)
+            };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.watches.insert(wd, dir.to_path_buf());
+            Ok(())
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> io::Result<Vec<Notification>> {
+            let mut notes = Vec::new();
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = timeout.as_secs()
+                .saturating_mul(1_000)
+                .saturating_add(u64::from(timeout.subsec_nanos() / 1_000_000));
+            let millis = if millis > c_int::max_value() as u64 {
+                c_int::max_value()
+            } else {
+                millis as c_int
+            };
+            let ready = unsafe { libc::poll(&mut pfd, 1, millis) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(notes);
+                }
+                return Err(err);
+            } else if ready == 0 {
+                return Ok(notes);
+            }
+
+            loop {
+                let len = unsafe {
+                    libc::read(self.fd, self.buf.as_mut_ptr() as *mut c_void, self.buf.len())
+                };
+                if len < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(notes);
+                    }
+                    return Err(err);
+                }
+                let len = len as usize;
+                let mut offset = 0;
+                while offset + EVENT_HEADER_SIZE <= len {
+                    let mut header = [0u32; 4];
+                    unsafe {
+                        ptr::copy_nonoverlapping(self.buf[offset..].as_ptr(),
+                                                 header.as_mut_ptr() as *mut u8,
+                                                 EVENT_HEADER_SIZE);
+                    }
+                    let wd = header[0] as c_int;
+                    let mask = header[1];
+                    let name_len = header[3] as usize;
+                    let name_start = offset + EVENT_HEADER_SIZE;
+                    let name_end = name_start + name_len;
+                    offset = name_end;
+
+                    if mask & IN_Q_OVERFLOW != 0 {
+                        notes.push(Notification::Overflow);
+                        continue;
+                    }
+                    let dir = match self.watches.get(&wd) {
+                        Some(dir) => dir,
+                        None => continue,
+                    };
+                    // the name is NUL padded out to name_len
+                    let name = &self.buf[name_start..name_end];
+                    let name = match name.iter().position(|b| *b == 0) {
+                        Some(nul) => &name[..nul],
+                        None => name,
+                    };
+                    if name.is_empty() {
+                        continue;
+                    }
+                    let path = normalize(&dir.join(OsStr::from_bytes(name)));
+                    if mask & (IN_CREATE | IN_MOVED_TO) != 0 {
+                        notes.push(Notification::Created(path));
+                    } else if mask & IN_MODIFY != 0 {
+                        notes.push(Notification::Modified(path));
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for Notifier {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+    use std::path::Path;
+    use std::time::Duration;
+    use super::Notification;
+
+    pub struct Notifier;
+
+    impl Notifier {
+        pub fn new() -> io::Result<Notifier> {
+            Err(io::Error::new(io::ErrorKind::Other,
+                               "file notification is only supported on linux"))
+        }
+
+        pub fn watch(&mut self, _dir: &Path) -> io::Result<()> {
+            unreachable!()
+        }
+
+        pub fn wait(&mut self, _timeout: Duration) -> io::Result<Vec<Notification>> {
+            unreachable!()
+        }
+    }
+}
+
+pub use self::imp::Notifier;
+
+/// Establish a notifier for the directory holding `glob`
+///
+/// Returns `None` if notification isn't possible, in which case the caller
+/// should poll.
+pub fn notifier_for(glob: &Path) -> Option<Notifier> {
+    let dir = match watch_directory(glob) {
+        Some(dir) => dir,
+        None => {
+            info!("cannot watch {:?} for changes, falling back to polling", glob);
+            return None;
+        }
+    };
+    match Notifier::new().and_then(|mut n| n.watch(&dir).map(|_| n)) {
+        Ok(n) => Some(n),
+        Err(e) => {
+            info!("cannot watch {:?} for changes, falling back to polling: {}", dir, e);
+            None
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use std::fs;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn watch_directory_of_glob() {
+        assert_eq!(watch_directory(Path::new("/var/log/*.log")),
+                   Some(PathBuf::from("/var/log")));
+        assert_eq!(watch_directory(Path::new("/var/log/app.log")),
+                   Some(PathBuf::from("/var/log")));
+        assert_eq!(watch_directory(Path::new("*.log")), Some(PathBuf::from(".")));
+        assert_eq!(watch_directory(Path::new("/var/log/*/app.log")), None);
+    }
+
+    #[test]
+    fn normalize_matches_glob_output() {
+        assert_eq!(normalize(&Path::new(".").join("a.log")), PathBuf::from("a.log"));
+        assert_eq!(normalize(Path::new("./logs/./a.log")), PathBuf::from("logs/a.log"));
+        assert_eq!(normalize(Path::new("/var/log/a.log")), PathBuf::from("/var/log/a.log"));
+    }
+
+    #[test]
+    fn notices_create_and_modify() {
+        let dir = TempDir::new("cernan_notify").unwrap();
+        let path = dir.path().join("a.log");
+        let mut notifier = notifier_for(&dir.path().join("*.log")).unwrap();
+
+        let mut fp = fs::File::create(&path).unwrap();
+        let notes = notifier.wait(Duration::from_millis(100)).unwrap();
+        assert!(notes.contains(&Notification::Created(path.clone())));
+
+        writeln!(fp, "hello").unwrap();
+        let notes = notifier.wait(Duration::from_millis(100)).unwrap();
+        assert!(notes.contains(&Notification::Modified(path)));
+
+        let notes = notifier.wait(Duration::from_millis(10)).unwrap();
+        assert!(notes.is_empty());
+    }
+}
@@ -4,7 +4,8 @@ mod file;
 mod flush;
 mod native;
 
-pub use self::file::{FileServer, FileServerConfig};
+pub use self::file::{Checkpointer, Encoding, FileServer, FileServerConfig, FileWatcher,
+                     MultilineConfig, StartPosition};
 pub use self::flush::FlushTimer;
 pub use self::graphite::{Graphite, GraphiteConfig};
 pub use self::native::{NativeServer, NativeServerConfig};
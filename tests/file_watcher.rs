@@ -0,0 +1,164 @@
+mod integration {
+    mod file_watcher {
+
+        extern crate cernan;
+        extern crate tempdir;
+
+        use self::cernan::source::{Checkpointer, FileWatcher, StartPosition};
+        use self::tempdir::TempDir;
+        use std::fs;
+        use std::io::Write;
+        use std::thread;
+        use std::time::Duration;
+
+        fn read_line(fw: &mut FileWatcher) -> Option<String> {
+            let mut buffer = Vec::new();
+            match fw.read_line(&mut buffer) {
+                Ok(sz) if sz > 0 => {
+                    buffer.pop();
+                    Some(String::from_utf8(buffer).unwrap())
+                }
+                _ => None,
+            }
+        }
+
+        #[test]
+        fn test_rename_rotation() {
+            let dir = TempDir::new("file_watcher").unwrap();
+            let path = dir.path().join("a.log");
+            let rotated_path = dir.path().join("a.log.1");
+            let checkpoints = Checkpointer::new(dir.path().join("checkpoints"));
+
+            let mut fp = fs::File::create(&path).unwrap();
+            let mut fw = FileWatcher::new(path.clone(), StartPosition::Beginning, &checkpoints)
+                .unwrap()
+                .rotation_grace(Duration::from_millis(100));
+
+            writeln!(fp, "before rotation").unwrap();
+            assert_eq!(read_line(&mut fw), Some("before rotation".to_string()));
+
+            fs::rename(&path, &rotated_path).unwrap();
+            let mut new_fp = fs::File::create(&path).unwrap();
+            writeln!(new_fp, "in the new file").unwrap();
+
+            // The watcher notices the rotation but keeps the old file open,
+            // picking up lines from writers that haven't reopened yet.
+            assert_eq!(read_line(&mut fw), None);
+            writeln!(fp, "late to the old file").unwrap();
+            assert_eq!(read_line(&mut fw), Some("late to the old file".to_string()));
+
+            thread::sleep(Duration::from_millis(150));
+            assert_eq!(read_line(&mut fw), Some("in the new file".to_string()));
+            assert_eq!(read_line(&mut fw), None);
+        }
+
+        #[test]
+        fn test_rename_rotation_without_new_file() {
+            let dir = TempDir::new("file_watcher").unwrap();
+            let path = dir.path().join("a.log");
+            let rotated_path = dir.path().join("a.log.1");
+            let checkpoints = Checkpointer::new(dir.path().join("checkpoints"));
+
+            let mut fp = fs::File::create(&path).unwrap();
+            let mut fw = FileWatcher::new(path.clone(), StartPosition::Beginning, &checkpoints)
+                .unwrap()
+                .rotation_grace(Duration::from_millis(0));
+
+            fs::rename(&path, &rotated_path).unwrap();
+            assert_eq!(read_line(&mut fw), None);
+
+            writeln!(fp, "still writing").unwrap();
+            assert_eq!(read_line(&mut fw), Some("still writing".to_string()));
+
+            let mut new_fp = fs::File::create(&path).unwrap();
+            writeln!(new_fp, "reopened").unwrap();
+            assert_eq!(read_line(&mut fw), Some("reopened".to_string()));
+        }
+
+        #[test]
+        fn test_copytruncate_rotation() {
+            let dir = TempDir::new("file_watcher").unwrap();
+            let path = dir.path().join("a.log");
+            let checkpoints = Checkpointer::new(dir.path().join("checkpoints"));
+
+            let mut fp = fs::File::create(&path).unwrap();
+            let mut fw = FileWatcher::new(path.clone(), StartPosition::Beginning, &checkpoints)
+                .unwrap();
+
+            writeln!(fp, "first line").unwrap();
+            writeln!(fp, "second line").unwrap();
+            assert_eq!(read_line(&mut fw), Some("first line".to_string()));
+            assert_eq!(read_line(&mut fw), Some("second line".to_string()));
+            assert_eq!(read_line(&mut fw), None);
+
+            let mut fp = fs::OpenOptions::new().write(true).truncate(true).open(&path).unwrap();
+            writeln!(fp, "third").unwrap();
+            assert_eq!(read_line(&mut fw), Some("third".to_string()));
+            assert_eq!(fw.pos, 6);
+        }
+
+        #[test]
+        fn test_invalid_utf8_does_not_stall() {
+            let dir = TempDir::new("file_watcher").unwrap();
+            let path = dir.path().join("a.log");
+            let checkpoints = Checkpointer::new(dir.path().join("checkpoints"));
+
+            let mut fp = fs::File::create(&path).unwrap();
+            let mut fw = FileWatcher::new(path.clone(), StartPosition::Beginning, &checkpoints)
+                .unwrap();
+
+            fp.write_all(b"bad \xff\xfe byte\nfine\n").unwrap();
+            let mut buffer = Vec::new();
+            assert_eq!(fw.read_line(&mut buffer).unwrap(), 12);
+            assert_eq!(buffer, b"bad \xff\xfe byte\n");
+            assert_eq!(read_line(&mut fw), Some("fine".to_string()));
+        }
+
+        #[test]
+        fn test_resume_from_checkpoint() {
+            let dir = TempDir::new("file_watcher").unwrap();
+            let path = dir.path().join("a.log");
+            let checkpoint_path = dir.path().join("checkpoints");
+
+            let mut fp = fs::File::create(&path).unwrap();
+            writeln!(fp, "first").unwrap();
+            writeln!(fp, "second").unwrap();
+            {
+                let mut checkpoints = Checkpointer::new(checkpoint_path.clone());
+                let mut fw = FileWatcher::new(path.clone(), StartPosition::Beginning, &checkpoints)
+                    .unwrap();
+                assert_eq!(read_line(&mut fw), Some("first".to_string()));
+                assert_eq!(read_line(&mut fw), Some("second".to_string()));
+                checkpoints.set(fw.file_id, fw.pos);
+                checkpoints.write().unwrap();
+            }
+
+            writeln!(fp, "third").unwrap();
+            writeln!(fp, "fourth").unwrap();
+
+            let mut checkpoints = Checkpointer::new(checkpoint_path);
+            checkpoints.read().unwrap();
+            let mut fw = FileWatcher::new(path.clone(), StartPosition::Checkpoint, &checkpoints)
+                .unwrap();
+            assert_eq!(read_line(&mut fw), Some("third".to_string()));
+            assert_eq!(read_line(&mut fw), Some("fourth".to_string()));
+            assert_eq!(read_line(&mut fw), None);
+        }
+
+        #[test]
+        fn test_start_position_end() {
+            let dir = TempDir::new("file_watcher").unwrap();
+            let path = dir.path().join("a.log");
+            let checkpoints = Checkpointer::new(dir.path().join("checkpoints"));
+
+            let mut fp = fs::File::create(&path).unwrap();
+            writeln!(fp, "already here").unwrap();
+            let mut fw = FileWatcher::new(path.clone(), StartPosition::End, &checkpoints)
+                .unwrap();
+
+            assert_eq!(read_line(&mut fw), None);
+            writeln!(fp, "new").unwrap();
+            assert_eq!(read_line(&mut fw), Some("new".to_string()));
+        }
+    }
+}
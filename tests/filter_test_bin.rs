@@ -0,0 +1,50 @@
+mod integration {
+    mod filter_test_bin {
+
+        extern crate serde_json;
+
+        use std::env;
+        use std::fs;
+        use std::io::Read;
+        use std::path::PathBuf;
+        use std::process::Command;
+
+        /// The cernan-filter-test binary cargo built alongside this test
+        fn filter_test_bin() -> PathBuf {
+            let mut path = env::current_exe().unwrap();
+            path.pop();
+            if path.ends_with("deps") {
+                path.pop();
+            }
+            path.join("cernan-filter-test")
+        }
+
+        fn json_lines(lines: &str) -> Vec<serde_json::Value> {
+            lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        }
+
+        #[test]
+        fn test_script_over_fixture() {
+            let mut fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            fixtures.push("resources/tests/filter_test");
+
+            let output = Command::new(filter_test_bin())
+                .arg("--script")
+                .arg(fixtures.join("tag_events.lua"))
+                .arg(fixtures.join("events.txt"))
+                .output()
+                .unwrap();
+            assert!(output.status.success(),
+                    "{}",
+                    String::from_utf8_lossy(&output.stderr));
+
+            let mut expected = String::new();
+            fs::File::open(fixtures.join("expected.txt"))
+                .unwrap()
+                .read_to_string(&mut expected)
+                .unwrap();
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert_eq!(json_lines(&stdout), json_lines(&expected));
+        }
+    }
+}
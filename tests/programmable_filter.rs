@@ -2,12 +2,34 @@ mod integration {
     mod programmable_filter {
 
         extern crate cernan;
+        extern crate tempdir;
 
-        use self::cernan::filter::{Filter, ProgrammableFilter, ProgrammableFilterConfig};
+        use self::cernan::filter::{ErrorPolicy, Filter, FilterError, ProgrammableFilter,
+                                   ProgrammableFilterConfig, ScriptArg};
         use self::cernan::metric;
-        use std::path::PathBuf;
+        use self::tempdir::TempDir;
+        use std::collections::HashMap;
+        use std::fs;
+        use std::io::{Read, Write};
+        use std::path::{Path, PathBuf};
         use std::sync::Arc;
 
+        fn write_script(path: &Path, body: &str) {
+            let mut fp = fs::File::create(path).unwrap();
+            fp.write_all(body.as_bytes()).unwrap();
+        }
+
+        fn version_script(version: &str) -> String {
+            format!("function process_metric(pyld)\n\
+                     \x20  payload.metric_set_tag(pyld, 1, \"version\", \"{}\")\n\
+                     end\n\
+                     function process_log(pyld)\n\
+                     end\n\
+                     function tick(pyld)\n\
+                     end\n",
+                    version)
+        }
+
         #[test]
         fn test_id_filter() {
             let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -15,11 +37,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.identity".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let metric = metric::Telemetry::new("identity", 12.0)
                 .overlay_tag("foo", "bar")
@@ -41,11 +65,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.remove_keys".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig_log = metric::LogLine::new("identity",
                                                 "i am the very model of the modern major general")
@@ -73,11 +99,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.remove_keys".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig_metric = metric::Telemetry::new("identity", 12.0)
                 .overlay_tag("foo", "bar")
@@ -102,11 +130,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.no_args_no_crash".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig_metric = metric::Telemetry::new("identity", 12.0)
                 .overlay_tag("foo", "bar")
@@ -129,11 +159,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.missing_func".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig_metric = metric::Telemetry::new("identity", 12.0)
                 .overlay_tag("foo", "bar")
@@ -147,6 +179,61 @@ mod integration {
             assert!(events.is_empty());
         }
 
+        #[test]
+        fn test_unmodified_events_are_not_copied() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/identity.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                config_path: "filters.identity".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig = Arc::new(Some(metric::Telemetry::new("identity", 12.0)));
+            let mut events = Vec::new();
+            let res = cs.process(metric::Event::Telemetry(orig.clone()), &mut events);
+            assert!(res.is_ok());
+            match events.pop().unwrap() {
+                metric::Event::Telemetry(m) => {
+                    assert_eq!(&*m as *const _, &*orig as *const _);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn test_missing_tick() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/no_tick.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::Drop,
+                config_path: "filters.no_tick".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let mut events = Vec::new();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            assert!(events.is_empty());
+
+            // errors are still reported on flush without a tick to run
+            let orig_event = metric::Event::new_telemetry(metric::Telemetry::new("identity",
+                                                                                 12.0));
+            assert!(cs.process(orig_event, &mut events).is_err());
+            events.clear();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            let errors = metric::Telemetry::new("cernan.filter.filters.no_tick.script_error", 1.0)
+                .aggr_sum();
+            assert_eq!(events, vec![metric::Event::new_telemetry(errors)]);
+        }
+
         #[test]
         fn test_add_log_tag_kv() {
             let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -154,11 +241,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.add_keys".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let expected_log = metric::LogLine::new("identity",
                                                     "i am the very model of the modern major \
@@ -186,11 +275,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.add_keys".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let expected_metric = metric::Telemetry::new("identity", 12.0)
                 .overlay_tag("foo", "bar")
@@ -214,11 +305,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.keep_count".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let metric0 = metric::Event::new_telemetry(metric::Telemetry::new("identity", 12.0));
             let metric1 = metric::Event::new_telemetry(metric::Telemetry::new("identity", 13.0));
@@ -273,11 +366,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.collectd_scrub".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig = "collectd.cernan-llrv-prod-b3fbb697.protocols-TcpExt.\
                         protocol_counter-TCPFastOpenActive";
@@ -312,11 +407,13 @@ mod integration {
 
             let config = ProgrammableFilterConfig {
                 script: script,
+                on_error: ErrorPolicy::PassThrough,
                 forwards: Vec::new(),
                 config_path: "filters.collectd_scrub".to_string(),
                 tags: Default::default(),
+                ..Default::default()
             };
-            let mut cs = ProgrammableFilter::new(config);
+            let mut cs = ProgrammableFilter::new(config).unwrap();
 
             let orig = "totally_fine.interface-lo.if_errors.tx 0 1478751126";
             let expected = "totally_fine.interface-lo.if_errors.tx 0 1478751126";
@@ -342,5 +439,769 @@ mod integration {
                 }
             }
         }
+
+        #[test]
+        fn test_drop_events() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/drop.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.drop".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let keep_metric = metric::Event::new_telemetry(metric::Telemetry::new("keep.me", 1.0));
+            let drop_metric = metric::Event::new_telemetry(metric::Telemetry::new("drop.me", 1.0));
+            let keep_log = metric::Event::new_log(metric::LogLine::new("app", "INFO started"));
+            let drop_log = metric::Event::new_log(metric::LogLine::new("app", "DEBUG x = 1"));
+
+            let mut events = Vec::new();
+            for ev in &[keep_metric.clone(), drop_metric, keep_log.clone(), drop_log] {
+                let res = cs.process(ev.clone(), &mut events);
+                assert!(res.is_ok());
+            }
+            assert_eq!(events, vec![keep_metric, keep_log]);
+        }
+
+        #[test]
+        fn test_clone_and_modify_metric() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/construct.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.construct".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_metric = metric::Telemetry::new("requests", 12.0)
+                .timestamp(100)
+                .overlay_tag("foo", "bar");
+            let expected_copy = metric::Telemetry::new("requests.copy", 12.0)
+                .timestamp(10101)
+                .aggr_sum()
+                .persist()
+                .overlay_tag("foo", "bar");
+            let orig_event = metric::Event::new_telemetry(orig_metric);
+
+            let mut events = Vec::new();
+            let res = cs.process(orig_event.clone(), &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0], orig_event);
+            assert_eq!(events[1], metric::Event::new_telemetry(expected_copy));
+        }
+
+        #[test]
+        fn test_modify_log_and_push_metric_table() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/construct.lua");
+
+            let mut tags = metric::TagMap::default();
+            tags.insert("host".to_string(), "web-1".to_string());
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.construct".to_string(),
+                tags: tags,
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_log = metric::LogLine::new("app", "hello").time(100);
+            let expected_log = metric::LogLine::new("app.upper", "HELLO").time(100);
+
+            let mut events = Vec::new();
+            let res = cs.process(metric::Event::new_log(orig_log), &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events, vec![metric::Event::new_log(expected_log)]);
+
+            events.clear();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            let expected_metric = metric::Telemetry::new("constructed", 2.5)
+                .aggr_set()
+                .timestamp(20202)
+                .overlay_tag("host", "web-1")
+                .overlay_tag("source", "tick");
+            assert_eq!(events, vec![metric::Event::new_telemetry(expected_metric)]);
+        }
+
+        #[test]
+        fn test_replace_all_metric_tags() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/scrub_tags.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.scrub_tags".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_metric = metric::Telemetry::new("identity", 12.0)
+                .overlay_tag("Host", "web-1")
+                .overlay_tag("k8s_pod", "web-1-x8f2")
+                .overlay_tag("k8s_namespace", "default");
+            let expected_metric = metric::Telemetry::new("identity", 12.0)
+                .overlay_tag("host", "web-1");
+            let orig_event = metric::Event::new_telemetry(orig_metric);
+            let expected_event = metric::Event::new_telemetry(expected_metric);
+
+            let mut events = Vec::new();
+            let res = cs.process(orig_event, &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events, vec![expected_event]);
+        }
+
+        #[test]
+        fn test_replace_all_log_tags() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/scrub_tags.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.scrub_tags".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_log = metric::LogLine::new("identity", "a log line")
+                .overlay_tag("SERVICE", "api")
+                .overlay_tag("k8s_pod", "api-1-aa0b");
+            let expected_log = metric::LogLine::new("identity", "a log line")
+                .overlay_tag("service", "api");
+            let orig_event = metric::Event::new_log(orig_log);
+            let expected_event = metric::Event::new_log(expected_log);
+
+            let mut events = Vec::new();
+            let res = cs.process(orig_event, &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events, vec![expected_event]);
+        }
+
+        #[test]
+        fn test_set_tags_reads_keys_and_values() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/set_tags.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                config_path: "filters.set_tags".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_metric = metric::Telemetry::new("identity", 12.0).overlay_tag("host", "web-1");
+            let expected_metric = metric::Telemetry::new("identity", 12.0)
+                .overlay_tag("env", "prod")
+                .overlay_tag("shard", "7");
+            let orig_log = metric::LogLine::new("identity", "a log line")
+                .overlay_tag("host", "web-1");
+            let expected_log = metric::LogLine::new("identity", "a log line")
+                .overlay_tag("env", "prod")
+                .overlay_tag("shard", "7");
+
+            let mut events = Vec::new();
+            let res = cs.process(metric::Event::new_telemetry(orig_metric), &mut events);
+            assert!(res.is_ok());
+            let res = cs.process(metric::Event::new_log(orig_log), &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events,
+                       vec![metric::Event::new_telemetry(expected_metric),
+                            metric::Event::new_log(expected_log)]);
+        }
+
+        #[test]
+        fn test_reload_swaps_only_valid_scripts() {
+            let dir = TempDir::new("cernan_reload").unwrap();
+            let script = dir.path().join("versioned.lua");
+            write_script(&script, &version_script("1"));
+
+            let config = ProgrammableFilterConfig {
+                script: script.clone(),
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.versioned".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let version = |cs: &mut ProgrammableFilter| {
+                let mut events = Vec::new();
+                let event = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+                assert!(cs.process(event, &mut events).is_ok());
+                match events.pop().unwrap() {
+                    metric::Event::Telemetry(mut m) => {
+                        let met = Arc::make_mut(&mut m).take().unwrap();
+                        met.tags.get(&"version".to_string()).cloned().unwrap()
+                    }
+                    _ => unreachable!(),
+                }
+            };
+            assert_eq!(version(&mut cs), "1");
+
+            write_script(&script, &version_script("2"));
+            assert!(cs.reload().is_ok());
+            assert_eq!(version(&mut cs), "2");
+
+            // syntax errors keep the running script
+            write_script(&script, "function process_metric(pyld)\n");
+            assert!(cs.reload().is_err());
+            assert_eq!(version(&mut cs), "2");
+
+            // as do scripts that handle no events, which `new` rejects too
+            write_script(&script, "function tick(pyld)\nend\n");
+            assert!(cs.reload().is_err());
+            assert_eq!(version(&mut cs), "2");
+            let config = ProgrammableFilterConfig {
+                script: script.clone(),
+                config_path: "filters.versioned".to_string(),
+                ..Default::default()
+            };
+            assert!(ProgrammableFilter::new(config).is_err());
+
+            // a script need not define every function to be swapped in
+            write_script(&script, "function process_log(pyld)\nend\n");
+            assert!(cs.reload().is_ok());
+        }
+
+        #[test]
+        fn test_load_error_is_not_fatal() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/syntax_error.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.syntax_error".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            assert!(ProgrammableFilter::new(config).is_err());
+        }
+
+        #[test]
+        fn test_runtime_error_pass_through() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/runtime_error.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                forwards: Vec::new(),
+                config_path: "filters.runtime_error".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_metric = metric::Telemetry::new("identity", 12.0).overlay_tag("foo", "bar");
+            let orig_event = metric::Event::new_telemetry(orig_metric);
+
+            let mut events = Vec::new();
+            match cs.process(orig_event.clone(), &mut events) {
+                Err(FilterError::ScriptError(msg, event)) => {
+                    assert!(msg.contains("metric went wrong"));
+                    // the event is passed on as it was before the script ran
+                    assert_eq!(event, vec![orig_event]);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+            assert!(events.is_empty());
+
+            let orig_log = metric::Event::new_log(metric::LogLine::new("identity", "a line"));
+            match cs.process(orig_log.clone(), &mut events) {
+                Err(FilterError::ScriptError(_, event)) => assert_eq!(event, vec![orig_log]),
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            // errors are counted and reported on flush
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            let errors = metric::Telemetry::new("cernan.filter.filters.runtime_error.script_error",
+                                                2.0)
+                .aggr_sum();
+            assert_eq!(events, vec![metric::Event::new_telemetry(errors)]);
+
+            events.clear();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            assert!(events.is_empty());
+        }
+
+        #[test]
+        fn test_failed_tick_reports_errors() {
+            let dir = TempDir::new("cernan_filter_tick").unwrap();
+            let script = dir.path().join("failing_tick.lua");
+            write_script(&script,
+                         "function process_metric(pyld)\n\
+                          \x20  error(\"metric went wrong\")\n\
+                          end\n\
+                          function tick(pyld)\n\
+                          \x20  error(\"tick went wrong\")\n\
+                          end\n");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                config_path: "filters.failing_tick".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let mut events = Vec::new();
+            let metric = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+            assert!(cs.process(metric, &mut events).is_err());
+
+            match cs.process(metric::Event::TimerFlush, &mut events) {
+                Err(FilterError::ScriptError(msg, report)) => {
+                    assert!(msg.contains("tick went wrong"), "{}", msg);
+                    let errors =
+                        metric::Telemetry::new("cernan.filter.filters.failing_tick.script_error",
+                                               2.0)
+                            .aggr_sum();
+                    assert_eq!(report, vec![metric::Event::new_telemetry(errors)]);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+            assert!(events.is_empty());
+        }
+
+        #[test]
+        fn test_index_out_of_range() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/out_of_range.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                config_path: "filters.out_of_range".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let mut events = Vec::new();
+            for func in &["clone_log",
+                          "clone_metric",
+                          "log_path",
+                          "log_set_path",
+                          "log_set_tags",
+                          "log_set_value",
+                          "log_tags",
+                          "log_value",
+                          "metric_name",
+                          "metric_set_aggr",
+                          "metric_set_persist",
+                          "metric_set_tags",
+                          "metric_set_timestamp",
+                          "metric_tags",
+                          "metric_value"] {
+                let orig_log = metric::Event::new_log(metric::LogLine::new("identity", *func));
+                match cs.process(orig_log.clone(), &mut events) {
+                    Err(FilterError::ScriptError(msg, event)) => {
+                        assert!(msg.contains("bad argument #2"), "{}: {}", func, msg);
+                        assert_eq!(event, vec![orig_log]);
+                    }
+                    other => panic!("unexpected result for {}: {:?}", func, other),
+                }
+            }
+
+            // dropping what isn't there is not an error
+            for func in &["drop_log", "drop_metric"] {
+                let orig_log = metric::Event::new_log(metric::LogLine::new("identity", *func));
+                assert!(cs.process(orig_log.clone(), &mut events).is_ok());
+                assert_eq!(events, vec![orig_log]);
+                events.clear();
+            }
+        }
+
+        #[test]
+        fn test_missing_string_arguments() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/missing_args.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                config_path: "filters.missing_args".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let mut events = Vec::new();
+            let cases = [("log_set_path", "#3"),
+                         ("log_set_value", "#3"),
+                         ("push_log", "#2"),
+                         ("push_metric", "#2")];
+            for &(func, arg) in &cases {
+                let orig_log = metric::Event::new_log(metric::LogLine::new("identity", func));
+                match cs.process(orig_log.clone(), &mut events) {
+                    Err(FilterError::ScriptError(msg, event)) => {
+                        assert!(msg.contains(&format!("bad argument {}", arg)),
+                                "{}: {}",
+                                func,
+                                msg);
+                        assert_eq!(event, vec![orig_log]);
+                    }
+                    other => panic!("unexpected result for {}: {:?}", func, other),
+                }
+            }
+
+            let orig_metric = metric::Event::new_telemetry(metric::Telemetry::new("identity",
+                                                                                  1.0));
+            match cs.process(orig_metric.clone(), &mut events) {
+                Err(FilterError::ScriptError(msg, event)) => {
+                    assert!(msg.contains("bad argument #3"), "{}", msg);
+                    assert_eq!(event, vec![orig_metric]);
+                }
+                other => panic!("unexpected result for metric_set_aggr: {:?}", other),
+            }
+
+            // a table without a name pushes nothing, rather than a metric
+            // named "nil"
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            let named = metric::Telemetry::new("named", 2.0).timestamp(10);
+            assert_eq!(events[0], metric::Event::new_telemetry(named));
+            // followed only by the count of script errors
+            assert_eq!(events.len(), 2);
+        }
+
+        #[test]
+        fn test_runtime_error_drop() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/runtime_error.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::Drop,
+                forwards: Vec::new(),
+                config_path: "filters.runtime_error".to_string(),
+                tags: Default::default(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let orig_event = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+
+            let mut events = Vec::new();
+            match cs.process(orig_event, &mut events) {
+                Err(FilterError::ScriptError(_, event)) => assert!(event.is_empty()),
+                other => panic!("unexpected result {:?}", other),
+            }
+            assert!(events.is_empty());
+        }
+
+        #[test]
+        fn test_infinite_loop_interrupted() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/infinite_loop.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                instruction_limit: Some(100_000),
+                config_path: "filters.infinite_loop".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let event = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+            let mut events = Vec::new();
+            match cs.process(event.clone(), &mut events) {
+                Err(FilterError::ScriptError(msg, passed)) => {
+                    assert!(msg.contains("instruction limit exceeded"));
+                    assert_eq!(passed, vec![event]);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            // the filter is still usable afterward
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+        }
+
+        #[test]
+        fn test_memory_limit() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/infinite_loop.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                instruction_limit: None,
+                memory_limit: Some(4 * 1024 * 1024),
+                config_path: "filters.infinite_loop".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let event = metric::Event::new_log(metric::LogLine::new("identity", "a line"));
+            let mut events = Vec::new();
+            match cs.process(event, &mut events) {
+                Err(FilterError::ScriptError(msg, _)) => assert!(msg.contains("memory")),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        #[test]
+        fn test_sandbox_hides_unsafe_libraries() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/sandbox_escape.lua");
+
+            let reachable = |sandbox: bool| {
+                let config = ProgrammableFilterConfig {
+                    script: script.clone(),
+                    sandbox: sandbox,
+                    config_path: "filters.sandbox_escape".to_string(),
+                    ..Default::default()
+                };
+                let mut cs = ProgrammableFilter::new(config).unwrap();
+                let event = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+                let mut events = Vec::new();
+                assert!(cs.process(event, &mut events).is_ok());
+                match events.pop().unwrap() {
+                    metric::Event::Telemetry(mut m) => {
+                        let met = Arc::make_mut(&mut m).take().unwrap();
+                        met.tags.get(&"reachable".to_string()).cloned().unwrap()
+                    }
+                    _ => unreachable!(),
+                }
+            };
+            assert_eq!(reachable(true), "");
+            assert_eq!(reachable(false), "io,os.execute,require,dofile,debug");
+        }
+
+        #[test]
+        fn test_default_config_keeps_standard_libraries() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/sandbox_escape.lua");
+
+            // scripts written before sandboxing must run unchanged
+            let config = ProgrammableFilterConfig {
+                script: script,
+                config_path: "filters.sandbox_escape".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+            let event = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+            let mut events = Vec::new();
+            assert!(cs.process(event, &mut events).is_ok());
+            match events.pop().unwrap() {
+                metric::Event::Telemetry(mut m) => {
+                    let met = Arc::make_mut(&mut m).take().unwrap();
+                    assert_eq!(met.tags.get(&"reachable".to_string()),
+                               Some(&"io,os.execute,require,dofile,debug".to_string()));
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn test_process_batch() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/collectd_scrub.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                batch_size: 3,
+                config_path: "filters.collectd_scrub".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+            assert_eq!(cs.batch_size(), 3);
+
+            let names = ["collectd.host-a.cpu", "app.requests", "collectd.host-b.memory"];
+            let batch = names.iter()
+                .map(|n| metric::Event::new_telemetry(metric::Telemetry::new(*n, 1.0)))
+                .collect();
+
+            let mut events = Vec::new();
+            let res = cs.process_batch(batch, &mut events);
+            assert!(res.is_ok());
+            let names: Vec<String> = events.into_iter()
+                .map(|e| match e {
+                    metric::Event::Telemetry(mut m) => Arc::make_mut(&mut m).take().unwrap().name,
+                    _ => unreachable!(),
+                })
+                .collect();
+            assert_eq!(names, vec!["collectd.cpu", "app.requests", "collectd.memory"]);
+        }
+
+        #[test]
+        fn test_process_batch_without_script_batch() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/runtime_error.lua");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                on_error: ErrorPolicy::PassThrough,
+                batch_size: 3,
+                config_path: "filters.runtime_error".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            // each event fails on its own, none are dropped
+            let batch = vec![metric::Event::new_telemetry(metric::Telemetry::new("a", 1.0)),
+                             metric::Event::new_log(metric::LogLine::new("some.path", "b")),
+                             metric::Event::new_telemetry(metric::Telemetry::new("c", 3.0))];
+            let mut events = Vec::new();
+            let res = cs.process_batch(batch.clone(), &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events, batch);
+
+            events.clear();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            let errors = metric::Telemetry::new("cernan.filter.filters.runtime_error.script_error",
+                                                3.0)
+                .aggr_sum();
+            assert_eq!(events, vec![metric::Event::new_telemetry(errors)]);
+        }
+
+        #[test]
+        fn test_state_survives_restart() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/running_total.lua");
+            let dir = TempDir::new("cernan_filter_state").unwrap();
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                data_directory: dir.path().to_path_buf(),
+                config_path: "filters.running_total".to_string(),
+                ..Default::default()
+            };
+            let total = |events: &mut Vec<metric::Event>| match events.pop().unwrap() {
+                metric::Event::Telemetry(mut m) => {
+                    let met = Arc::make_mut(&mut m).take().unwrap();
+                    assert_eq!(met.tags.get(&"last_seen".to_string()),
+                               Some(&"requests".to_string()));
+                    met.value().unwrap()
+                }
+                _ => unreachable!(),
+            };
+
+            let mut events = Vec::new();
+            {
+                let mut cs = ProgrammableFilter::new(config.clone()).unwrap();
+                for value in &[1.0, 2.0, 3.0] {
+                    let metric = metric::Telemetry::new("requests", *value);
+                    let res = cs.process(metric::Event::new_telemetry(metric), &mut events);
+                    assert!(res.is_ok());
+                }
+                assert!(events.is_empty());
+                let res = cs.process(metric::Event::TimerFlush, &mut events);
+                assert!(res.is_ok());
+                assert_eq!(total(&mut events), 6.0);
+            }
+
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            assert_eq!(total(&mut events), 6.0);
+
+            let metric = metric::Telemetry::new("requests", 4.0);
+            let res = cs.process(metric::Event::new_telemetry(metric), &mut events);
+            assert!(res.is_ok());
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            assert_eq!(total(&mut events), 10.0);
+        }
+
+        #[test]
+        fn test_state_key_must_be_given() {
+            let dir = TempDir::new("cernan_filter_state").unwrap();
+            let script = dir.path().join("nil_key.lua");
+            write_script(&script,
+                         "function process_log(pyld)\n\
+                          \x20  payload.state_set(pyld, nil, payload.log_value(pyld, 1))\n\
+                          end\n\
+                          function process_metric(pyld)\n\
+                          \x20  payload.state_set(pyld, \"kept\", \"yes\")\n\
+                          end\n");
+
+            let config = ProgrammableFilterConfig {
+                script: script,
+                data_directory: dir.path().to_path_buf(),
+                config_path: "filters.nil_key".to_string(),
+                ..Default::default()
+            };
+            let mut cs = ProgrammableFilter::new(config).unwrap();
+
+            let mut events = Vec::new();
+            let log = metric::Event::new_log(metric::LogLine::new("identity", "lost"));
+            match cs.process(log, &mut events) {
+                Err(FilterError::ScriptError(msg, _)) => {
+                    assert!(msg.contains("bad argument #2"), "{}", msg);
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+            let metric = metric::Event::new_telemetry(metric::Telemetry::new("identity", 1.0));
+            assert!(cs.process(metric, &mut events).is_ok());
+            assert!(cs.process(metric::Event::TimerFlush, &mut events).is_ok());
+
+            let mut state = String::new();
+            fs::File::open(dir.path().join("filter_state").join("filters.nil_key"))
+                .unwrap()
+                .read_to_string(&mut state)
+                .unwrap();
+            assert!(state.contains("\"kept\":\"yes\""), "{}", state);
+            assert!(!state.contains("lost"), "{}", state);
+        }
+
+        #[test]
+        fn test_shared_script_with_args() {
+            let mut script = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            script.push("resources/tests/scripts/threshold.lua");
+
+            let config = |label: &str, threshold: f64| {
+                let mut args = HashMap::new();
+                args.insert("label".to_string(), ScriptArg::String(label.to_string()));
+                args.insert("threshold".to_string(), ScriptArg::Float(threshold));
+                ProgrammableFilterConfig {
+                    script: script.clone(),
+                    args: args,
+                    config_path: format!("filters.{}_threshold", label),
+                    ..Default::default()
+                }
+            };
+            let mut web = ProgrammableFilter::new(config("web", 10.0)).unwrap();
+            let mut db = ProgrammableFilter::new(config("db", 100.0)).unwrap();
+
+            let event = metric::Event::new_telemetry(metric::Telemetry::new("latency", 50.0));
+
+            let mut events = Vec::new();
+            let res = web.process(event.clone(), &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events.len(), 1);
+            match events.pop().unwrap() {
+                metric::Event::Telemetry(mut m) => {
+                    let met = Arc::make_mut(&mut m).take().unwrap();
+                    assert_eq!(met.tags.get(&"label".to_string()), Some(&"web".to_string()));
+                }
+                _ => unreachable!(),
+            }
+
+            let res = db.process(event, &mut events);
+            assert!(res.is_ok());
+            assert!(events.is_empty());
+        }
     }
 }
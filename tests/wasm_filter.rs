@@ -0,0 +1,102 @@
+#![cfg(feature = "wasm")]
+
+mod integration {
+    mod wasm_filter {
+
+        extern crate cernan;
+        extern crate tempdir;
+
+        use self::cernan::filter::{Filter, FilterError, WasmFilter, WasmFilterConfig};
+        use self::cernan::metric;
+        use self::tempdir::TempDir;
+        use std::fs;
+        use std::io::Read;
+        use std::path::PathBuf;
+
+        #[test]
+        fn test_identity_module() {
+            let mut module = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            module.push("resources/tests/wasm/identity.wasm");
+            let dir = TempDir::new("cernan_wasm_filter").unwrap();
+
+            let config = WasmFilterConfig {
+                module: module,
+                data_directory: dir.path().to_path_buf(),
+                config_path: "filters.identity".to_string(),
+                ..Default::default()
+            };
+            let mut cs = WasmFilter::new(config).unwrap();
+
+            let metric = metric::Telemetry::new("identity", 12.0)
+                .aggr_sum()
+                .timestamp(101)
+                .overlay_tag("foo", "bar");
+            let event = metric::Event::new_telemetry(metric);
+            let log = metric::Event::new_log(metric::LogLine::new("identity", "dropped"));
+
+            let mut events = Vec::new();
+            for _ in 0..2 {
+                let res = cs.process(event.clone(), &mut events);
+                assert!(res.is_ok());
+            }
+            let res = cs.process(log, &mut events);
+            assert!(res.is_ok());
+            assert_eq!(events, vec![event.clone(), event]);
+
+            events.clear();
+            let res = cs.process(metric::Event::TimerFlush, &mut events);
+            assert!(res.is_ok());
+            assert!(events.is_empty());
+
+            let mut state = String::new();
+            fs::File::open(dir.path().join("filter_state").join("filters.identity"))
+                .unwrap()
+                .read_to_string(&mut state)
+                .unwrap();
+            assert!(state.contains("\"seen\":2"));
+        }
+
+        #[test]
+        fn test_instruction_limit() {
+            let mut module = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            module.push("resources/tests/wasm/spin.wasm");
+            let dir = TempDir::new("cernan_wasm_filter").unwrap();
+
+            let config = WasmFilterConfig {
+                module: module,
+                data_directory: dir.path().to_path_buf(),
+                config_path: "filters.spin".to_string(),
+                instruction_limit: Some(10_000),
+                ..Default::default()
+            };
+            let mut cs = WasmFilter::new(config).unwrap();
+
+            let event = metric::Event::new_telemetry(metric::Telemetry::new("spin", 1.0));
+            let mut events = Vec::new();
+            match cs.process(event.clone(), &mut events) {
+                Err(FilterError::ScriptError(msg, pass_through)) => {
+                    assert!(msg.contains("instruction limit exceeded"), "{}", msg);
+                    assert_eq!(pass_through, vec![event]);
+                }
+                other => panic!("expected a script error, got {:?}", other),
+            }
+
+            // the limit is per call, so the module is still usable after
+            let log = metric::Event::new_log(metric::LogLine::new("spin", "fine"));
+            assert!(cs.process(log, &mut events).is_ok());
+
+            // a flush whose tick fails still reports the errors, its own
+            // included
+            match cs.process(metric::Event::TimerFlush, &mut events) {
+                Err(FilterError::ScriptError(msg, report)) => {
+                    assert!(msg.contains("instruction limit exceeded"), "{}", msg);
+                    let errors = metric::Telemetry::new("cernan.filter.filters.spin.script_error",
+                                                        2.0)
+                        .aggr_sum();
+                    assert_eq!(report, vec![metric::Event::new_telemetry(errors)]);
+                }
+                other => panic!("expected a script error, got {:?}", other),
+            }
+        }
+    }
+}